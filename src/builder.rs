@@ -0,0 +1,208 @@
+//! Configuring a credential store in one place.
+
+use std::{fmt, io::BufRead, path::Path, sync::Arc};
+
+use crate::{
+    creds::{Creds, CredsError},
+    format::{self, ReadOptions, WriteOptions, DEFAULT_DELIMITER},
+    hash::HashParams,
+    hasher::{PasswordHasher, SharedHasher},
+    policy::PasswordPolicy,
+};
+
+/// A builder for [`Creds`] stores with non-default settings.
+///
+/// Created by [`Creds::builder`], as in
+/// `Creds::builder().case_insensitive(true).delimiter(b'|').build()`.
+///
+/// Every setting is kept by the built store, including the
+/// [delimiter](CredsBuilder::delimiter), which the store's own methods like
+/// [`Creds::write`] and [`Creds::reload_from_file`] then use. Read existing
+/// files with [`CredsBuilder::read`] or [`CredsBuilder::read_from_file`] to
+/// get a store with these settings.
+#[derive(Clone)]
+pub struct CredsBuilder {
+    case_insensitive: bool,
+    delimiter: char,
+    policy: PasswordPolicy,
+    hash_params: HashParams,
+    hasher: Option<SharedHasher>,
+    capacity: usize,
+}
+
+impl CredsBuilder {
+    /// Create a builder with the default settings of [`Creds::new`].
+    pub fn new() -> Self {
+        Self {
+            case_insensitive: false,
+            delimiter: DEFAULT_DELIMITER,
+            policy: PasswordPolicy::default(),
+            hash_params: HashParams::default(),
+            hasher: None,
+            capacity: 0,
+        }
+    }
+
+    /// Sets whether usernames are matched case-insensitively.
+    ///
+    /// Defaults to `false`. See [`Creds::set_case_insensitive`].
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Sets the character separating usernames from passwords in files.
+    ///
+    /// Defaults to `:`. See [`Creds::set_delimiter`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the delimiter is a newline.
+    pub fn delimiter(mut self, delimiter: impl Into<char>) -> Self {
+        self.delimiter = format::check_delimiter(delimiter.into());
+        self
+    }
+
+    /// Sets the policy checked by [`Creds::insert_checked`].
+    ///
+    /// Defaults to an empty policy. See [`Creds::set_password_policy`].
+    pub fn policy(mut self, policy: PasswordPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the parameters used by [`Creds::insert_hashed`].
+    ///
+    /// Defaults to [`HashParams::default`]. See [`Creds::set_hash_params`].
+    pub fn hash_params(mut self, params: HashParams) -> Self {
+        self.hash_params = params;
+        self
+    }
+
+    /// Sets the hasher used by [`Creds::insert_hashed`], instead of Argon2id
+    /// with the [hash parameters](CredsBuilder::hash_params).
+    ///
+    /// See [`Creds::set_hasher`].
+    pub fn hasher(mut self, hasher: impl PasswordHasher + Send + Sync + 'static) -> Self {
+        self.hasher = Some(Arc::new(hasher));
+        self
+    }
+
+    /// Sets how many credentials to allocate room for.
+    ///
+    /// Defaults to 0. See [`Creds::with_capacity`].
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Creates an empty store with these settings.
+    pub fn build(&self) -> Creds {
+        let mut creds = Creds::with_capacity(self.capacity);
+        self.configure(&mut creds);
+        creds
+    }
+
+    /// Returns options for reading files with the configured delimiter.
+    pub fn reader(&self) -> ReadOptions {
+        ReadOptions::new()
+            .delimiter(self.delimiter)
+            .capacity(self.capacity)
+    }
+
+    /// Returns options for writing files with the configured delimiter.
+    pub fn writer(&self) -> WriteOptions {
+        WriteOptions::new().delimiter(self.delimiter)
+    }
+
+    /// Parses a store with these settings from the given reader.
+    ///
+    /// See also: [`Creds::read`]
+    pub fn read(&self, reader: impl BufRead) -> Result<Creds, CredsError> {
+        let mut creds = self.reader().read(reader)?;
+        self.configure(&mut creds);
+        Ok(creds)
+    }
+
+    /// Parses a store with these settings from the given file.
+    ///
+    /// See also: [`Creds::read_from_file`]
+    pub fn read_from_file(&self, path: impl AsRef<Path>) -> Result<Creds, CredsError> {
+        let mut creds = self.reader().read_from_file(path)?;
+        self.configure(&mut creds);
+        Ok(creds)
+    }
+
+    fn configure(&self, creds: &mut Creds) {
+        creds.set_case_insensitive(self.case_insensitive);
+        creds.set_delimiter(self.delimiter);
+        creds.set_password_policy(self.policy.clone());
+        creds.set_hash_params(self.hash_params);
+        creds.set_shared_hasher(self.hasher.clone());
+    }
+}
+
+impl fmt::Debug for CredsBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CredsBuilder")
+            .field("case_insensitive", &self.case_insensitive)
+            .field("delimiter", &self.delimiter)
+            .field("policy", &self.policy)
+            .field("hash_params", &self.hash_params)
+            .field("hasher", &self.hasher.is_some())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl Default for CredsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::BcryptHasher;
+
+    #[test]
+    fn builds_a_case_insensitive_pipe_delimited_store() {
+        let mut creds = Creds::builder()
+            .case_insensitive(true)
+            .delimiter('|')
+            .build();
+        assert!(creds.is_case_insensitive());
+        assert_eq!(creds.delimiter(), '|');
+
+        creds.insert("Alice", "secret");
+        assert!(creds.verify("alice", "secret"));
+        assert!(creds.verify("ALICE", "secret"));
+        assert_eq!(creds.to_string_contents().unwrap(), "Alice|secret\n");
+        assert!(creds.try_insert("a|b", "secret").is_err());
+        assert!(creds.try_insert("a:b", "secret").is_ok());
+
+        let read = Creds::builder()
+            .case_insensitive(true)
+            .delimiter('|')
+            .read("Alice|secret\n".as_bytes())
+            .unwrap();
+        assert!(read.verify("alice", "secret"));
+        assert_eq!(read.delimiter(), '|');
+    }
+
+    #[test]
+    fn builds_a_store_with_a_policy_and_hasher() {
+        let policy = PasswordPolicy::new().min_length(8);
+        let mut creds = Creds::builder()
+            .policy(policy.clone())
+            .hasher(BcryptHasher::new(4).unwrap())
+            .build();
+        assert_eq!(creds.password_policy(), &policy);
+        assert!(creds.insert_checked("alice", "short").is_err());
+
+        creds.insert_hashed("alice", "secret");
+        assert!(creds.get("alice").unwrap().starts_with("$2y$04$"));
+        assert!(creds.verify("alice", "secret"));
+    }
+}
@@ -1,18 +1,89 @@
 //! Simple credential management.
 
 use std::{
-    fs::File,
-    io::{self, BufRead, BufReader, Write},
-    ops::Index,
+    cmp::Ordering,
+    error::Error,
+    ffi::OsString,
+    fmt,
+    io::{self, BufRead, Read, Write},
+    mem,
+    ops::{Deref, DerefMut, Index},
     path::Path,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::SystemTime,
 };
 
 use indexmap::IndexMap;
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::Zeroizing;
+
+use crate::{
+    builder::CredsBuilder,
+    format::{self, DuplicatePolicy, FileFormat, ReadOptions, WriteOptions, DEFAULT_DELIMITER},
+    generate::{self, Charset, PasswordSpec},
+    hash::{self, HashParams},
+    hasher::{self, PasswordHasher, SharedHasher},
+    htpasswd,
+    meta::CredMeta,
+    policy::{PasswordPolicy, PolicyViolation},
+    username::{Username, UsernameRef},
+};
+
+/// A stored password, which is zeroed out when dropped, and its metadata.
+///
+/// This dereferences to the password itself.
+#[derive(Clone, Default)]
+pub(crate) struct Password {
+    secret: Zeroizing<String>,
+    meta: CredMeta,
+}
+
+impl Deref for Password {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.secret
+    }
+}
+
+impl DerefMut for Password {
+    fn deref_mut(&mut self) -> &mut String {
+        &mut self.secret
+    }
+}
+
+/// Moves a password out of its zeroizing wrapper, for handing it to the caller.
+fn into_plain(mut password: Password) -> String {
+    mem::take(&mut *password.secret)
+}
 
 /// A credential store that stores username/password pairs.
-#[derive(Default, Debug, Clone)]
+///
+/// Stored passwords are overwritten with zeros before their memory is freed,
+/// whether that happens because the store is dropped or cleared, or because a
+/// password is overwritten by [`Creds::insert`]. Passwords that are handed
+/// back to the caller, for example by [`Creds::remove`] or by iterating over
+/// an owned store, are the caller's responsibility. The temporary copies made
+/// while reading and writing credential files are zeroed as well, although
+/// buffers owned by the underlying readers and writers are out of reach.
+///
+/// The [`Debug`](fmt::Debug) output lists usernames but redacts passwords, so
+/// a store can be logged without leaking secrets.
+///
+/// With the `serde` feature enabled, `Creds` implements `Serialize` and
+/// `Deserialize` as a map from usernames to passwords, in insertion order.
+#[derive(Clone)]
 pub struct Creds {
-    map: IndexMap<String, String>,
+    map: IndexMap<Username, Password>,
+    hash_params: HashParams,
+    hasher: Option<SharedHasher>,
+    password_policy: PasswordPolicy,
+    case_insensitive: bool,
+    delimiter: char,
+    /// A hash of the empty password made by [`Creds::insert_hashed`], to
+    /// verify against for missing usernames.
+    dummy_hash: OnceLock<String>,
 }
 
 impl Creds {
@@ -20,22 +91,428 @@ impl Creds {
     pub fn new() -> Self {
         Self {
             map: IndexMap::new(),
+            hash_params: HashParams::default(),
+            hasher: None,
+            password_policy: PasswordPolicy::default(),
+            case_insensitive: false,
+            delimiter: DEFAULT_DELIMITER,
+            dummy_hash: OnceLock::new(),
+        }
+    }
+
+    /// Create a new empty credential store with room for at least `capacity`
+    /// credentials before it reallocates.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            map: IndexMap::with_capacity(capacity),
+            ..Self::new()
+        }
+    }
+
+    /// Create a new empty credential store with case-insensitive usernames.
+    ///
+    /// See [`Creds::set_case_insensitive`].
+    pub fn new_case_insensitive() -> Self {
+        let mut creds = Self::new();
+        creds.case_insensitive = true;
+        creds
+    }
+
+    /// Returns `true` if usernames are matched case-insensitively.
+    pub fn is_case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    /// Sets whether usernames are matched case-insensitively.
+    ///
+    /// In case-insensitive mode, usernames are compared by their lowercase
+    /// form when they are inserted and looked up, so `Alice` and `alice` refer
+    /// to the same credential. Each character is lowercased with
+    /// [`char::to_lowercase`], the Unicode lowercase mapping, so non-ASCII
+    /// usernames like `Héllo` and `héllo` also match. This is not full Unicode
+    /// case folding, so for example `straße` and `STRASSE` are different
+    /// usernames.
+    ///
+    /// The store keeps the casing a username was first inserted with, and
+    /// that is what iteration returns and what [`Creds::write`] writes.
+    /// Inserting `alice` after `Alice` replaces the password but keeps
+    /// `Alice`; use [`Creds::rename`] to change the casing.
+    ///
+    /// Enabling this on a store merges usernames that only differ in case. The
+    /// password of the last one wins, and the entry keeps the position and
+    /// casing of the first one.
+    pub fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        if case_insensitive != self.case_insensitive {
+            self.case_insensitive = case_insensitive;
+            let map = mem::take(&mut self.map);
+            for (username, password) in map {
+                let username = self.owned_key(username.name);
+                self.map.insert(username, password);
+            }
         }
     }
 
+    /// Converts a username into the key it is looked up with.
+    fn key<'a>(&self, username: &'a str) -> UsernameRef<'a> {
+        UsernameRef::new(username, self.case_insensitive)
+    }
+
+    /// Like [`Creds::key`], but for owned usernames.
+    fn owned_key(&self, username: String) -> Username {
+        Username::new(username, self.case_insensitive)
+    }
+
+    /// Returns the character separating usernames from passwords when this
+    /// store is written.
+    pub fn delimiter(&self) -> char {
+        self.delimiter
+    }
+
+    /// Sets the character separating usernames from passwords when this
+    /// store is written, and when it is [reloaded](Creds::reload_from_file).
+    ///
+    /// Defaults to `:`, or to the delimiter of the
+    /// [options](ReadOptions::delimiter) the store was read with. This is
+    /// used by the methods of the store itself, like [`Creds::write`] and
+    /// [`Creds::write_to_file`]; [`WriteOptions`] use their own delimiter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the delimiter is a newline.
+    pub fn set_delimiter(&mut self, delimiter: char) {
+        self.delimiter = format::check_delimiter(delimiter);
+    }
+
+    /// Returns options for reading files in the format of this store.
+    pub(crate) fn read_options(&self) -> ReadOptions {
+        ReadOptions::new().delimiter(self.delimiter)
+    }
+
+    /// Returns options for writing files in the format of this store.
+    pub(crate) fn write_options(&self) -> WriteOptions {
+        WriteOptions::new().delimiter(self.delimiter)
+    }
+
+    /// Sets the hasher used by [`Creds::insert_hashed`], instead of Argon2id
+    /// with the store's [hash parameters](Creds::set_hash_params).
+    ///
+    /// Existing hashes are not changed, and still verify.
+    pub fn set_hasher(&mut self, hasher: impl PasswordHasher + Send + Sync + 'static) {
+        self.set_shared_hasher(Some(Arc::new(hasher)));
+    }
+
+    /// Sets the hasher used by [`Creds::insert_hashed`], or Argon2id with
+    /// `None`.
+    pub(crate) fn set_shared_hasher(&mut self, hasher: Option<SharedHasher>) {
+        self.hasher = hasher;
+        self.dummy_hash = OnceLock::new();
+    }
+
+    /// Returns the parameters used by [`Creds::insert_hashed`].
+    pub fn hash_params(&self) -> &HashParams {
+        &self.hash_params
+    }
+
+    /// Sets the parameters used by [`Creds::insert_hashed`].
+    ///
+    /// This only affects passwords hashed afterwards; existing hashes keep the
+    /// parameters they were created with, and still verify.
+    pub fn set_hash_params(&mut self, params: HashParams) {
+        self.hash_params = params;
+        self.dummy_hash = OnceLock::new();
+    }
+
+    /// Returns the policy checked by [`Creds::insert_checked`].
+    pub fn password_policy(&self) -> &PasswordPolicy {
+        &self.password_policy
+    }
+
+    /// Sets the policy checked by [`Creds::insert_checked`].
+    ///
+    /// This does not check the passwords that are already stored.
+    pub fn set_password_policy(&mut self, policy: PasswordPolicy) {
+        self.password_policy = policy;
+    }
+
+    /// Returns the number of credentials in the store.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the store contains no credentials.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the number of credentials the store can hold without
+    /// reallocating.
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    /// Shrinks the capacity of the store as much as possible, for example
+    /// after removing many credentials.
+    pub fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit();
+    }
+
     /// Remove all stored credentials. This leaves the cred store empty.
+    ///
+    /// The store keeps its allocated [capacity](Creds::capacity), so it can be
+    /// filled again without reallocating. Use [`Creds::clear_and_shrink`] to
+    /// release the memory as well.
     pub fn clear(&mut self) {
         self.map.clear();
     }
 
+    /// Remove all stored credentials and release the memory they used.
+    pub fn clear_and_shrink(&mut self) {
+        self.clear();
+        self.shrink_to_fit();
+    }
+
     /// Add a new username and password pair.
     ///
-    /// The username should not contain newlines or the colon `:` character,
-    /// and the password should not contain newlines.
+    /// The username should not contain line breaks or the colon `:`
+    /// character, should not start with `#`, and the password should not
+    /// contain line breaks. Line breaks are `\n` and `\r`.
     ///
     /// If a password already exists for the given username, it will be overwritten.
+    /// The current time is recorded as the [modification time](CredMeta::modified)
+    /// of the credential, and also as its [creation time](CredMeta::created) if
+    /// it is new. The other inserting methods do the same.
     pub fn insert(&mut self, username: impl Into<String>, password: impl Into<String>) {
-        self.map.insert(username.into(), password.into());
+        let username = self.owned_key(username.into());
+        self.store(username, Zeroizing::new(password.into()));
+    }
+
+    /// Stores a password, recording the time in its metadata, and returns the
+    /// previous one.
+    fn store(&mut self, username: Username, secret: Zeroizing<String>) -> Option<Password> {
+        let now = SystemTime::now();
+        match self.map.entry(username) {
+            indexmap::map::Entry::Occupied(mut entry) => {
+                let mut meta = entry.get().meta.clone();
+                meta.touch(now);
+                Some(entry.insert(Password { secret, meta }))
+            }
+            indexmap::map::Entry::Vacant(entry) => {
+                entry.insert(Password {
+                    secret,
+                    meta: CredMeta::new_at(now),
+                });
+                None
+            }
+        }
+    }
+
+    /// Stores a password read from a file, with the metadata read along with
+    /// it.
+    pub(crate) fn insert_read(&mut self, username: String, password: String, meta: CredMeta) {
+        let username = self.owned_key(username);
+        let secret = Zeroizing::new(password);
+        self.map.insert(username, Password { secret, meta });
+    }
+
+    /// Add a new username and password pair, after checking that they can be
+    /// written with [`Creds::write`].
+    ///
+    /// Returns [`InvalidCredential`] without changing the store if the
+    /// username is empty, contains the store's [delimiter](Creds::set_delimiter)
+    /// or a line break (`\n` or `\r`), or starts with the comment character
+    /// `#`, or if the password contains a line break. Otherwise, returns the
+    /// previous password for the username, if there was one.
+    pub fn try_insert(
+        &mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Result<Option<String>, InvalidCredential> {
+        let username = username.into();
+        let password = Zeroizing::new(password.into());
+        if username.is_empty() {
+            return Err(InvalidCredential::EmptyUsername);
+        }
+        let comment = format::DEFAULT_COMMENT;
+        if username.contains([self.delimiter, '\n', '\r'])
+            || username.trim_start().starts_with(comment)
+        {
+            return Err(InvalidCredential::IllegalUsername {
+                username,
+                delimiter: self.delimiter,
+                comment,
+            });
+        }
+        if password.contains(['\n', '\r']) {
+            return Err(InvalidCredential::IllegalPassword { username });
+        }
+        let username = self.owned_key(username);
+        Ok(self.store(username, password).map(into_plain))
+    }
+
+    /// Add a new username and password pair, if the password follows the
+    /// store's [password policy](Creds::set_password_policy).
+    ///
+    /// Returns a [`PolicyViolation`] listing every rule the password breaks,
+    /// without changing the store. [`Creds::insert`] does not check the
+    /// policy.
+    pub fn insert_checked(
+        &mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Result<(), PolicyViolation> {
+        let policy = self.password_policy.clone();
+        self.insert_checked_with(username, password, &policy)
+    }
+
+    /// Add a new username and password pair, if the password follows the
+    /// given policy instead of the store's own.
+    ///
+    /// See also: [`Creds::insert_checked`], [`PasswordPolicy::validate`]
+    pub fn insert_checked_with(
+        &mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+        policy: &PasswordPolicy,
+    ) -> Result<(), PolicyViolation> {
+        let password = Zeroizing::new(password.into());
+        policy.validate(&password)?;
+        let username = self.owned_key(username.into());
+        self.store(username, password);
+        Ok(())
+    }
+
+    /// Add a new username with a random password following `spec`, and
+    /// return the password.
+    ///
+    /// See [`generate_password`](generate::generate_password). The returned
+    /// copy is the caller's responsibility, for example to show it to the user
+    /// once. If a password already exists for the given username, it will be
+    /// overwritten.
+    pub fn insert_generated(&mut self, username: impl Into<String>, spec: &PasswordSpec) -> String {
+        let password = generate::generate_password(spec);
+        self.insert(username, password.clone());
+        password
+    }
+
+    /// Add a new username with a random password of `len` ASCII letters,
+    /// digits, and symbols, and return the password.
+    ///
+    /// See also: [`Creds::insert_generated`]
+    pub fn insert_random(&mut self, username: impl Into<String>, len: usize) -> String {
+        self.insert_generated(username, &PasswordSpec::new().length(len))
+    }
+
+    /// Generates a random password of `len` characters from the given
+    /// character set.
+    ///
+    /// This is a shortcut for [`generate_password`](generate::generate_password)
+    /// and does not change any store.
+    pub fn generate_password(len: usize, charset: Charset) -> String {
+        generate::generate_password(&PasswordSpec::new().length(len).charset(charset))
+    }
+
+    /// Add a new username and password pair that expires at the given time.
+    ///
+    /// From then on, [`Creds::verify`] rejects the password, and
+    /// [`Creds::prune_expired`] removes it. The credential is still returned
+    /// by [`Creds::get`] and written to files.
+    ///
+    /// **The expiry time is only kept in files written and read with
+    /// [metadata](crate::meta) enabled**, through
+    /// [`WriteOptions::metadata`] and [`ReadOptions::metadata`]. Otherwise
+    /// the credential verifies again once its file is read back. Inserting
+    /// the username again with another method keeps the expiry time.
+    pub fn insert_with_expiry(
+        &mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+        expires_at: SystemTime,
+    ) {
+        let username = username.into();
+        self.insert(username.as_str(), password);
+        if let Some(stored) = self.map.get_mut(&self.key(&username)) {
+            stored.meta.set_expires(Some(expires_at));
+        }
+    }
+
+    /// Removes every credential that expires at or before `now`, and returns
+    /// how many were removed.
+    ///
+    /// The remaining entries keep their relative insertion order.
+    pub fn prune_expired(&mut self, now: SystemTime) -> usize {
+        let len = self.map.len();
+        self.map
+            .retain(|_, password| !password.meta.is_expired(now));
+        len - self.map.len()
+    }
+
+    /// Add a new username and password pair, storing an Argon2id hash of the
+    /// password instead of the password itself.
+    ///
+    /// A random salt is generated for each entry, and the store's
+    /// [hash parameters](Creds::set_hash_params) are used, unless another
+    /// [hasher](Creds::set_hasher) is set. The resulting PHC
+    /// string is what [`Creds::get`] returns and what [`Creds::write`] writes,
+    /// so hashed entries can be mixed with plaintext ones in the same file.
+    ///
+    /// If a password already exists for the given username, it will be overwritten.
+    pub fn insert_hashed(&mut self, username: impl Into<String>, password: &str) {
+        let hash = self.hash(password);
+        let username = self.owned_key(username.into());
+        self.store(username, Zeroizing::new(hash));
+    }
+
+    /// Hashes the given password like [`Creds::insert_hashed`].
+    fn hash(&self, password: &str) -> String {
+        match &self.hasher {
+            Some(hasher) => hasher.hash(password),
+            None => hash::hash_password(password, &self.hash_params),
+        }
+    }
+
+    /// Checks the given password against a fixed dummy hash, made with the
+    /// same hasher and parameters as [`Creds::insert_hashed`], and returns
+    /// `false`. This makes a missing username cost as much as a wrong
+    /// password for a freshly hashed entry.
+    pub(crate) fn reject_with_dummy_hash(&self, password: &str) -> bool {
+        let dummy_hash = self.dummy_hash.get_or_init(|| self.hash(""));
+        let _ = hasher::verify(password, dummy_hash);
+        false
+    }
+
+    /// Replaces every plaintext password with an Argon2id hash of it, using
+    /// the given hash parameters, and returns how many were replaced.
+    ///
+    /// Only Argon2, bcrypt, and scrypt hashes are left as they are. Every
+    /// credential still [verifies](Creds::verify) with the same password
+    /// afterwards, and keeps its position and metadata; write the store back
+    /// to its file to complete the migration.
+    ///
+    /// `$apr1$` and `{SHA}` hashes from `.htpasswd` files are too weak to
+    /// keep, but cannot be hashed again without the password. If the store
+    /// holds any, this returns [`CredsError::WeakHash`] for the first one and
+    /// changes nothing. Replace them first, for example with
+    /// [`Creds::change_password`], or remove them.
+    pub fn upgrade_plaintext_to_hashed(
+        &mut self,
+        params: &HashParams,
+    ) -> Result<usize, CredsError> {
+        if let Some((username, _)) = self
+            .iter()
+            .find(|(_, stored)| htpasswd::is_weak_hash(stored))
+        {
+            return Err(CredsError::WeakHash {
+                username: username.to_owned(),
+            });
+        }
+        let mut upgraded = 0;
+        for stored in self.map.values_mut() {
+            if !hasher::is_recognized(stored) {
+                stored.secret = Zeroizing::new(hash::hash_password(stored, params));
+                upgraded += 1;
+            }
+        }
+        Ok(upgraded)
     }
 
     /// Retrieve a stored password for the given username.
@@ -44,29 +521,507 @@ impl Creds {
     /// Otherwise, the username does not exist in the store, and `None` is
     /// returned.
     pub fn get(&self, username: &str) -> Option<&str> {
-        self.map.get(username).map(String::as_str)
+        self.map
+            .get(&self.key(username))
+            .map(|password| password.as_str())
+    }
+
+    /// Retrieve the metadata of the given username's credential.
+    ///
+    /// Returns `None` if the username does not exist in the store. See the
+    /// [`meta`](crate::meta) module for what is recorded.
+    pub fn metadata(&self, username: &str) -> Option<&CredMeta> {
+        self.map
+            .get(&self.key(username))
+            .map(|password| &password.meta)
+    }
+
+    /// Retrieve the metadata of the given username's credential, for setting
+    /// its expiry time or note.
+    ///
+    /// Returns `None` if the username does not exist in the store.
+    pub fn metadata_mut(&mut self, username: &str) -> Option<&mut CredMeta> {
+        self.map
+            .get_mut(&self.key(username))
+            .map(|password| &mut password.meta)
+    }
+
+    /// Retrieve the stored passwords for several usernames at once.
+    ///
+    /// The returned passwords are in the same order as the given usernames,
+    /// with `None` for each username that does not exist in the store.
+    pub fn get_many<'a>(&'a self, usernames: &[&str]) -> Vec<Option<&'a str>> {
+        usernames
+            .iter()
+            .map(|username| self.get(username))
+            .collect()
+    }
+
+    /// Retrieve a mutable reference to the stored password for the given
+    /// username, to change it in place.
+    ///
+    /// The caller is responsible for not introducing newlines, which would
+    /// make the store fail to [write](Creds::write).
+    ///
+    /// The stored password is zeroed out when it is removed or the store is
+    /// dropped, but only its final buffer is. Assigning a new `String` drops
+    /// the old one without zeroing it, and growing the string may reallocate
+    /// and leave a copy behind; prefer [`Creds::insert`] to replace a password
+    /// outright.
+    pub fn get_mut(&mut self, username: &str) -> Option<&mut String> {
+        self.map
+            .get_mut(&self.key(username))
+            .map(|password| &mut **password)
+    }
+
+    /// Returns the username as it is spelled in the store, which differs from
+    /// the given one in case-insensitive stores.
+    pub(crate) fn stored_username(&self, username: &str) -> Option<&str> {
+        self.map
+            .get_key_value(&self.key(username))
+            .map(|(username, _)| username.name.as_str())
+    }
+
+    /// Returns `true` if the store contains a credential for the given
+    /// username.
+    pub fn contains(&self, username: &str) -> bool {
+        self.map.contains_key(&self.key(username))
+    }
+
+    /// Checks whether the given password matches the one stored for the given
+    /// username.
+    ///
+    /// Entries stored with [`Creds::insert_hashed`] (or any stored password
+    /// that is an Argon2 PHC string) are checked against the hash, as are
+    /// entries using one of the [`.htpasswd` hash schemes](crate::htpasswd).
+    /// The algorithm is chosen by the hash's prefix with
+    /// [`hasher::verify`], so a store may mix hashes of different kinds.
+    /// Note that this means a plaintext password that happens to look like
+    /// such a hash, for example by starting with `$argon2` or `{SHA}`, is
+    /// treated as a hash.
+    ///
+    /// Plaintext passwords are compared in constant time: the comparison does
+    /// not short-circuit on the first differing byte or on a length mismatch,
+    /// and its running time depends only on the length of the given password.
+    /// A missing username performs the same comparison work as a wrong
+    /// plaintext password, so the two cases cannot be told apart by timing.
+    /// If the store holds hashes, a missing username is instead checked
+    /// against a dummy hash made with the store's
+    /// [hasher](Creds::set_hasher) or [hash parameters](Creds::set_hash_params),
+    /// so that it costs as much as a wrong password for an entry hashed with
+    /// the same settings. Entries hashed with other costs take a different
+    /// time to check, which timing can reveal.
+    /// Prefer this over comparing the result of [`Creds::get`] directly when
+    /// authenticating users.
+    ///
+    /// Credentials that have [expired](Creds::insert_with_expiry) are
+    /// rejected, after the same comparison work.
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        let matches = self.matches(username, password);
+        let expired = self
+            .metadata(username)
+            .is_some_and(|meta| meta.is_expired(SystemTime::now()));
+        matches & !expired
+    }
+
+    /// Like [`Creds::verify`], but ignoring expiry.
+    fn matches(&self, username: &str, password: &str) -> bool {
+        let (found, stored) = match self.get(username) {
+            Some(stored) => match hasher::verify(password, stored) {
+                Some(matches) => return matches,
+                None => (Choice::from(1), stored),
+            },
+            None => {
+                if self
+                    .map
+                    .values()
+                    .any(|stored| hasher::is_recognized(stored))
+                {
+                    return self.reject_with_dummy_hash(password);
+                }
+                (Choice::from(0), password)
+            }
+        };
+        (found & constant_time_eq(stored.as_bytes(), password.as_bytes())).into()
+    }
+
+    /// Verify several username and password pairs at once.
+    ///
+    /// The results are in the same order as the given pairs. Each pair is
+    /// checked with [`Creds::verify`] in turn, so the batch takes as long as
+    /// checking the pairs one by one, and is timing-safe to the same extent:
+    /// a wrong password takes as long as the right one, but a hash with
+    /// higher costs takes longer to check than one with lower costs.
+    pub fn verify_all<'a>(&self, pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<bool> {
+        pairs
+            .into_iter()
+            .map(|(username, password)| self.verify(username, password))
+            .collect()
+    }
+
+    /// Change the password of the given username, if `old` matches the
+    /// current one.
+    ///
+    /// The old password is checked like with [`Creds::verify`], so the check
+    /// takes the same time whether the username is missing or the password is
+    /// wrong. An [expired](Creds::insert_with_expiry) credential cannot be
+    /// changed this way: given the right old password, this returns
+    /// [`ChangeError::Expired`], so that callers can tell it apart from a
+    /// wrong password.
+    ///
+    /// If the current password is a hash, the new one is hashed with the same
+    /// scheme: Argon2id with the store's
+    /// [hash parameters](Creds::set_hash_params), bcrypt or scrypt with the
+    /// same costs, or `$apr1$`. Unsalted `{SHA}` hashes are too weak to keep,
+    /// so they are replaced with bcrypt. A plaintext password is replaced with
+    /// plaintext. The entry keeps its position in the store.
+    pub fn change_password(
+        &mut self,
+        username: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<(), ChangeError> {
+        let matches = self.matches(username, old);
+        let expired = self
+            .metadata(username)
+            .is_some_and(|meta| meta.is_expired(SystemTime::now()));
+        if !matches || expired {
+            let username = username.to_owned();
+            return Err(if !self.contains(&username) {
+                ChangeError::UserNotFound { username }
+            } else if !matches {
+                ChangeError::WrongPassword { username }
+            } else {
+                ChangeError::Expired { username }
+            });
+        }
+        let hash_params = self.hash_params;
+        if let Some(stored) = self.map.get_mut(&self.key(username)) {
+            let rehashed = hasher::rehash(new, stored, &hash_params);
+            stored.secret = Zeroizing::new(rehashed.unwrap_or_else(|| new.to_owned()));
+            stored.meta.touch(SystemTime::now());
+        }
+        Ok(())
+    }
+
+    /// Get the given username's entry in the store for in-place manipulation.
+    ///
+    /// Inserting through a vacant entry appends it to the end of the store,
+    /// just like [`Creds::insert`]. Modifying an occupied entry keeps it in
+    /// its current position.
+    ///
+    /// This looks the username up only once, so "use the existing password or
+    /// store a new one" is a single call. With [`Entry::or_insert_with`], the
+    /// new password is only computed if the username is not already present,
+    /// as in `creds.entry(username).or_insert_with(generate_password)`.
+    pub fn entry(&mut self, username: impl Into<String>) -> Entry<'_> {
+        let username = self.owned_key(username.into());
+        match self.map.entry(username) {
+            indexmap::map::Entry::Occupied(inner) => Entry::Occupied(OccupiedEntry { inner }),
+            indexmap::map::Entry::Vacant(inner) => Entry::Vacant(VacantEntry { inner }),
+        }
+    }
+
+    /// Retrieve the stored password for the given username, or store and
+    /// return the one computed by `default` if the username does not exist.
+    ///
+    /// `default` is only called if the username is not already present. This
+    /// is a shortcut for `creds.entry(username).or_insert_with(default)`; see
+    /// [`Creds::entry`].
+    pub fn get_or_insert_with(
+        &mut self,
+        username: impl Into<String>,
+        default: impl FnOnce() -> String,
+    ) -> &str {
+        self.entry(username).or_insert_with(default)
+    }
+
+    /// Remove the credential for the given username, returning its password.
+    ///
+    /// If the username does not exist in the store, `None` is returned.
+    ///
+    /// This uses shift-remove semantics: the remaining entries keep their
+    /// relative insertion order, at the cost of shifting every entry after the
+    /// removed one. This keeps the output of [`Creds::write`] stable apart from
+    /// the removed line.
+    pub fn remove(&mut self, username: &str) -> Option<String> {
+        self.map.shift_remove(&self.key(username)).map(into_plain)
+    }
+
+    /// Remove the credential for the given username, returning the stored
+    /// username and password.
+    ///
+    /// Like [`Creds::remove`], this preserves the insertion order of the
+    /// remaining entries.
+    pub fn remove_entry(&mut self, username: &str) -> Option<(String, String)> {
+        self.map
+            .shift_remove_entry(&self.key(username))
+            .map(|(username, password)| (username.name, into_plain(password)))
+    }
+
+    /// Keeps only the credentials for which `keep` returns `true`, given the
+    /// username and password, and removes the rest.
+    ///
+    /// The retained entries keep their relative insertion order.
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&str, &str) -> bool,
+    {
+        self.map
+            .retain(|username, password| keep(&username.name, password));
+    }
+
+    /// Changes the username of a credential, keeping its password and its
+    /// position in insertion order.
+    ///
+    /// Returns [`RenameError::NotFound`] if `old` does not exist, or
+    /// [`RenameError::AlreadyExists`] if `new` already belongs to another
+    /// credential. The store is left unchanged on error.
+    pub fn rename(&mut self, old: &str, new: impl Into<String>) -> Result<(), RenameError> {
+        let new = self.owned_key(new.into());
+        let index = match self.map.get_index_of(&self.key(old)) {
+            Some(index) => index,
+            None => {
+                return Err(RenameError::NotFound {
+                    username: old.to_owned(),
+                })
+            }
+        };
+        match self.map.get_index_of(&new) {
+            // Only the casing changes, which does not move the entry.
+            Some(existing) if existing == index => {
+                let (username, _) = self.map.get_index_mut(index).unwrap();
+                *username = new;
+                return Ok(());
+            }
+            Some(_) => return Err(RenameError::AlreadyExists { username: new.name }),
+            None => {}
+        }
+        let password = mem::take(&mut self.map[index]);
+        self.map.insert(new, password);
+        // Swapping out the old entry moves the new one, which was appended
+        // last, into its position.
+        self.map.swap_remove_index(index);
+        Ok(())
+    }
+
+    /// Inserts all credentials from `other` into this store, overwriting the
+    /// password of any username that exists in both.
+    ///
+    /// This is the same as [`Creds::merge_with`] with [`MergePolicy::Overwrite`].
+    pub fn merge(&mut self, other: Creds) {
+        for (username, password) in other.map {
+            let username = self.owned_key(username.name);
+            self.map.insert(username, password);
+        }
+    }
+
+    /// Inserts clones of all credentials from `other` into this store,
+    /// overwriting the password of any username that exists in both.
+    pub fn merge_ref(&mut self, other: &Creds) {
+        self.merge(other.clone());
+    }
+
+    /// Inserts all credentials from `other` into this store, resolving
+    /// usernames that exist in both according to `policy`.
+    ///
+    /// Existing usernames keep their position. New usernames are appended in
+    /// the order they appear in `other`.
+    ///
+    /// With [`MergePolicy::Error`], returns a [`MergeConflict`] naming the
+    /// first username in `other` that already exists, and leaves the store
+    /// unchanged. The other policies never return an error.
+    pub fn merge_with(&mut self, other: Creds, policy: MergePolicy) -> Result<(), MergeConflict> {
+        if policy == MergePolicy::Error {
+            let conflict = other
+                .map
+                .keys()
+                .find(|username| self.map.contains_key(&self.key(&username.name)));
+            if let Some(username) = conflict {
+                return Err(MergeConflict {
+                    username: username.name.clone(),
+                });
+            }
+        }
+        for (username, password) in other.map {
+            let username = self.owned_key(username.name);
+            match self.map.entry(username) {
+                indexmap::map::Entry::Occupied(mut entry) => match policy {
+                    MergePolicy::KeepExisting | MergePolicy::Error => {}
+                    MergePolicy::Overwrite => {
+                        entry.insert(password);
+                    }
+                },
+                indexmap::map::Entry::Vacant(entry) => {
+                    entry.insert(password);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Combines two stores that were both changed from a common `base`, like
+    /// two copies of a credentials file edited separately.
+    ///
+    /// For each username, a change made on only one side is applied, and
+    /// changes made the same way on both sides are kept. Adding and removing
+    /// a username count as changes too. If both sides changed the same
+    /// username differently, it is reported as a [`Conflict`], and every
+    /// conflict is returned instead of the merged store.
+    ///
+    /// The merged store has the settings of `ours`, and lists the usernames
+    /// of `ours` in their order, followed by those only in `theirs`.
+    pub fn merge3(base: &Creds, ours: &Creds, theirs: &Creds) -> Result<Creds, Vec<Conflict>> {
+        let mut merged = Creds {
+            map: IndexMap::with_capacity(ours.len().max(theirs.len())),
+            hash_params: ours.hash_params,
+            hasher: ours.hasher.clone(),
+            password_policy: ours.password_policy.clone(),
+            case_insensitive: ours.case_insensitive,
+            delimiter: ours.delimiter,
+            dummy_hash: OnceLock::new(),
+        };
+        let mut conflicts = Vec::new();
+        let usernames = ours.usernames().chain(
+            theirs
+                .usernames()
+                .filter(|username| !ours.contains(username)),
+        );
+        for username in usernames {
+            let in_ours = ours.map.get(&ours.key(username));
+            let in_theirs = theirs.map.get(&theirs.key(username));
+            let base_password = base.get(username);
+            let ours_password = in_ours.map(|password| password.as_str());
+            let theirs_password = in_theirs.map(|password| password.as_str());
+            let chosen = if ours_password == theirs_password || theirs_password == base_password {
+                in_ours
+            } else if ours_password == base_password {
+                in_theirs
+            } else {
+                conflicts.push(Conflict {
+                    username: username.to_owned(),
+                    base: base_password.map(str::to_owned),
+                    ours: ours_password.map(str::to_owned),
+                    theirs: theirs_password.map(str::to_owned),
+                });
+                continue;
+            };
+            if let Some(password) = chosen {
+                let username = merged.owned_key(username.to_owned());
+                merged.map.insert(username, password.clone());
+            }
+        }
+        if conflicts.is_empty() {
+            Ok(merged)
+        } else {
+            Err(conflicts)
+        }
+    }
+
+    /// Compares this store against `other`, listing the usernames that would
+    /// have to be added, removed, or given a new password to turn this store
+    /// into `other`.
+    ///
+    /// Only usernames are reported, never passwords.
+    pub fn diff(&self, other: &Creds) -> CredsDiff {
+        let mut diff = CredsDiff::default();
+        for (username, password) in self {
+            match other.get(username) {
+                None => diff.removed.push(username.to_owned()),
+                Some(other_password) if other_password != password => {
+                    diff.changed.push(username.to_owned())
+                }
+                Some(_) => {}
+            }
+        }
+        for username in other.usernames() {
+            if !self.contains(username) {
+                diff.added.push(username.to_owned());
+            }
+        }
+        diff
+    }
+
+    /// Returns an iterator over the stored username/password pairs, in
+    /// insertion order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            inner: self.map.iter(),
+        }
+    }
+
+    /// Like [`Creds::iter`], but with the metadata of each entry.
+    pub(crate) fn iter_meta(&self) -> impl Iterator<Item = (&str, &str, &CredMeta)> {
+        self.map
+            .iter()
+            .map(|(username, password)| (username.name.as_str(), password.as_str(), &password.meta))
+    }
+
+    /// Returns an iterator over the stored usernames, in insertion order.
+    pub fn usernames(&self) -> Usernames<'_> {
+        Usernames {
+            inner: self.map.keys(),
+        }
+    }
+
+    /// Returns a builder for a store with non-default settings, as in
+    /// `Creds::builder().case_insensitive(true).build()`.
+    pub fn builder() -> CredsBuilder {
+        CredsBuilder::new()
+    }
+
+    /// Returns the default options for reading a credential store, which can
+    /// then be customized, as in `Creds::reader().delimiter(b'\t').read(reader)`.
+    pub fn reader() -> ReadOptions {
+        ReadOptions::new()
+    }
+
+    /// Returns the default options for writing a credential store, which can
+    /// then be customized, as in `Creds::writer().delimiter(b'\t').write(&creds, writer)`.
+    pub fn writer() -> WriteOptions {
+        WriteOptions::new()
     }
 
     /// Writes the credentials into the given writer.
     ///
-    /// The output format is:
+    /// Entries are written in insertion order. The output format is:
     ///
     /// ```text
     /// <username1>:<password1>
     /// <username2>:<password2>
     /// ...
     /// ```
-    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
-        for (username, password) in &self.map {
-            if username.contains(':') || username.contains('\n') || password.contains('\n') {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "username or password contains illegal characters",
-                ));
-            }
-            writeln!(writer, "{}:{}", username, password)?;
-        }
-        Ok(())
+    ///
+    /// with the store's [delimiter](Creds::set_delimiter) in place of `:`.
+    ///
+    /// Returns [`CredsError::IllegalCharacter`] if a username contains the
+    /// delimiter or a line break (`\n` or `\r`) or would be read back as a
+    /// comment, or if a password contains a line break.
+    ///
+    /// See [`Creds::writer`] for more options.
+    pub fn write(&self, writer: impl Write) -> Result<(), CredsError> {
+        self.write_options().write(self, writer)
+    }
+
+    /// Writes the credentials into the given writer, sorted by username.
+    ///
+    /// This gives the same output for the same credentials, whatever order
+    /// they were inserted in. See [`WriteOptions::sort_keys`].
+    pub fn write_sorted(&self, writer: impl Write) -> Result<(), CredsError> {
+        self.write_options().sort_keys(true).write(self, writer)
+    }
+
+    /// Writes the credentials into the given writer, sorted by username with
+    /// the given comparison function.
+    ///
+    /// For example, `creds.write_sorted_by(writer, |a, b| b.cmp(a))` writes
+    /// them in reverse order. See [`WriteOptions::write_sorted_by`].
+    pub fn write_sorted_by(
+        &self,
+        writer: impl Write,
+        compare: impl FnMut(&str, &str) -> Ordering,
+    ) -> Result<(), CredsError> {
+        self.write_options().write_sorted_by(self, writer, compare)
     }
 
     /// Parses a credentials file from the given reader.
@@ -78,37 +1033,833 @@ impl Creds {
     /// <username2>:<password2>
     /// ...
     /// ```
-    pub fn read(reader: impl BufRead) -> io::Result<Self> {
-        let mut creds = Self::new();
-        for line_result in reader.lines() {
-            let line = line_result?;
-            if let Some((username, password)) = line.split_once(':') {
-                creds.insert(username, password);
-            }
-        }
-        Ok(creds)
+    ///
+    /// Lines may end with `\n` or `\r\n`, so files edited on Windows read the
+    /// same. Blank lines and comment lines, whose first non-whitespace
+    /// character is `#`, are skipped. Other lines without a `:` separator are
+    /// skipped too; see [`Creds::read_strict`] to reject them instead. See
+    /// [`Line`](crate::format::Line) for the exact rules.
+    /// Whitespace around usernames and passwords is kept as part of them; see
+    /// [`ReadOptions::trim_whitespace`] to remove it.
+    /// To keep the comments and layout of a file when writing it back, read
+    /// it as a [`Document`](crate::document::Document) instead.
+    ///
+    /// I/O errors from the reader are reported as [`CredsError::ReadLine`],
+    /// with the 1-based number of the line that was being read.
+    ///
+    /// See [`Creds::reader`] for more options.
+    pub fn read(reader: impl BufRead) -> Result<Self, CredsError> {
+        ReadOptions::new().read(reader)
     }
 
-    /// Writes the credential store to the given file.
+    /// Parses a credentials file from the given reader, rejecting malformed
+    /// lines.
     ///
-    /// See also: [`Creds::write`]
-    pub fn write_to_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
-        let file = File::create(path)?;
-        self.write(file)
+    /// This accepts the same format as [`Creds::read`], but where `read`
+    /// skips [malformed](crate::format::Line::Malformed) lines, this returns a
+    /// [`CredsError::MalformedLine`] error naming the 1-based line number of
+    /// the first such line. Blank lines and comments are still allowed
+    /// anywhere.
+    ///
+    /// Like `read`, I/O errors are reported as [`CredsError::ReadLine`] with
+    /// the number of the line that was being read.
+    pub fn read_strict(reader: impl BufRead) -> Result<Self, CredsError> {
+        ReadOptions::new().strict(true).read(reader)
     }
 
-    /// Parses a credential store from the given file.
+    /// Parses a credentials file from the given reader, rejecting duplicate
+    /// usernames.
     ///
-    /// See also: [`Creds::read`]
-    pub fn read_from_file(path: impl AsRef<Path>) -> io::Result<Self> {
-        let file = File::open(path)?;
-        Self::read(BufReader::new(file))
+    /// Where [`Creds::read`] lets the last line for a username win, this
+    /// returns a [`CredsError::DuplicateUsername`] error naming the 1-based
+    /// line number and username of the first repeated username. See
+    /// [`ReadOptions::duplicates`] for other options.
+    pub fn read_no_duplicates(reader: impl BufRead) -> Result<Self, CredsError> {
+        ReadOptions::new()
+            .duplicates(DuplicatePolicy::Error)
+            .read(reader)
     }
-}
 
-impl<S> Index<S> for Creds
-where
-    S: AsRef<str>,
+    /// Parses a credentials file that separates usernames from passwords with
+    /// the given delimiter instead of `:`.
+    ///
+    /// This is a shortcut for `Creds::reader().delimiter(delimiter).read(reader)`.
+    /// The store keeps the [delimiter](Creds::set_delimiter), so writing it
+    /// back with [`Creds::write`] uses the same format.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the delimiter is a newline.
+    pub fn read_with_delimiter(reader: impl BufRead, delimiter: char) -> Result<Self, CredsError> {
+        ReadOptions::new().delimiter(delimiter).read(reader)
+    }
+
+    /// Writes the credentials, separating usernames from passwords with the
+    /// given delimiter instead of `:`.
+    ///
+    /// Returns [`CredsError::IllegalCharacter`] if a username contains the
+    /// delimiter. This is a shortcut for
+    /// `Creds::writer().delimiter(delimiter).write(&creds, writer)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the delimiter is a newline.
+    pub fn write_with_delimiter(
+        &self,
+        writer: impl Write,
+        delimiter: char,
+    ) -> Result<(), CredsError> {
+        WriteOptions::new().delimiter(delimiter).write(self, writer)
+    }
+
+    /// Parses a credential store from the contents of a credentials file.
+    ///
+    /// See also: [`Creds::read`]. This is also available through [`FromStr`],
+    /// as in `"user:pass\n".parse::<Creds>()`.
+    pub fn from_str_contents(contents: &str) -> Result<Self, CredsError> {
+        Self::read(contents.as_bytes())
+    }
+
+    /// Loads credentials from the environment variables of this process.
+    ///
+    /// Every variable whose name starts with `prefix` becomes an entry: the
+    /// rest of the name is the username, and the value is the password. For
+    /// example, with the prefix `CRED_`, the variable `CRED_alice=secret` adds
+    /// the password `secret` for `alice`.
+    ///
+    /// The prefix is matched case-sensitively, and the usernames keep the case
+    /// of the variable names. An empty prefix loads every variable. Variables
+    /// that are not valid Unicode, or whose name is just the prefix, are
+    /// skipped.
+    pub fn from_env(prefix: &str) -> Self {
+        Self::from_vars(std::env::vars_os(), prefix)
+    }
+
+    /// Loads credentials from the given environment variables, like
+    /// [`Creds::from_env`].
+    ///
+    /// This is separate so that tests can pass in variables without changing
+    /// the environment of the whole process, which other threads may be
+    /// reading.
+    fn from_vars(vars: impl IntoIterator<Item = (OsString, OsString)>, prefix: &str) -> Self {
+        vars.into_iter()
+            .filter_map(|(name, value)| {
+                let name = name.into_string().ok()?;
+                let value = value.into_string().ok()?;
+                let username = name.strip_prefix(prefix).filter(|name| !name.is_empty())?;
+                Some((username.to_owned(), value))
+            })
+            .collect()
+    }
+
+    /// Writes the credential store into a string, in the format of a
+    /// credentials file.
+    ///
+    /// See also: [`Creds::write`]
+    pub fn to_string_contents(&self) -> Result<String, CredsError> {
+        let mut contents = Vec::new();
+        self.write(&mut contents)?;
+        Ok(String::from_utf8(contents).expect("credentials are written as UTF-8"))
+    }
+
+    /// Writes the credential store to the given file.
+    ///
+    /// The file is replaced atomically, so it is never left partially written;
+    /// see [`WriteOptions::write_to_file`].
+    ///
+    /// See also: [`Creds::write`]
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> Result<(), CredsError> {
+        self.write_options().write_to_file(self, path)
+    }
+
+    /// Appends the credentials to the end of the given file, without
+    /// rewriting its existing contents.
+    ///
+    /// Usernames already in the file are not updated but written again, which
+    /// [`Creds::read`] resolves in favor of the last line; see
+    /// [`WriteOptions::append_to_file`] for the tradeoffs.
+    pub fn append_to_file(&self, path: impl AsRef<Path>) -> Result<(), CredsError> {
+        self.write_options().append_to_file(self, path)
+    }
+
+    /// Parses a credential store from the given file.
+    ///
+    /// See also: [`Creds::read`]
+    pub fn read_from_file(path: impl AsRef<Path>) -> Result<Self, CredsError> {
+        ReadOptions::new().read_from_file(path)
+    }
+
+    /// Finds the password for a single username in the given file, without
+    /// reading the other credentials into memory.
+    ///
+    /// This scans the file line by line and stops at the first line for the
+    /// username, so memory use does not grow with the size of the file. Each
+    /// lookup still reads up to the whole file, so this is meant for finding
+    /// one username and stopping; to look up many, use
+    /// [`Creds::read_from_file`] once instead.
+    ///
+    /// If the file has several lines for the username, this returns the
+    /// password on the first one, while [`Creds::read_from_file`] keeps the
+    /// last one. See [`ReadOptions::lookup`] to choose.
+    pub fn lookup_in_file(
+        path: impl AsRef<Path>,
+        username: &str,
+    ) -> Result<Option<String>, CredsError> {
+        ReadOptions::new()
+            .duplicates(DuplicatePolicy::KeepFirst)
+            .lookup_in_file(path, username)
+    }
+
+    /// Replaces the credentials with the ones parsed from the given file.
+    ///
+    /// The file is read [strictly](Creds::read_strict), and the store is only
+    /// changed if the whole file is read successfully, so on error, including
+    /// a malformed line, it keeps its old credentials. Its settings, like its
+    /// [hash parameters](Creds::set_hash_params) and
+    /// [case sensitivity](Creds::set_case_insensitive), are kept, and the file
+    /// is read with its [delimiter](Creds::set_delimiter).
+    pub fn reload_from_file(&mut self, path: impl AsRef<Path>) -> Result<(), CredsError> {
+        let fresh = self.read_options().strict(true).read_from_file(path)?;
+        self.replace_entries(fresh);
+        Ok(())
+    }
+
+    /// Replaces the credentials with the ones of `fresh`, keeping the
+    /// settings of this store.
+    pub(crate) fn replace_entries(&mut self, mut fresh: Creds) {
+        fresh.set_case_insensitive(self.case_insensitive);
+        self.map = fresh.map;
+    }
+
+    /// Parses a credential store from the given file, rejecting malformed
+    /// lines.
+    ///
+    /// See also: [`Creds::read_strict`]
+    pub fn read_strict_from_file(path: impl AsRef<Path>) -> Result<Self, CredsError> {
+        ReadOptions::new().strict(true).read_from_file(path)
+    }
+
+    /// Parses an Apache `.htpasswd` file from the given reader.
+    ///
+    /// `.htpasswd` files use the same `<username>:<hash>` format, so this is
+    /// the same as [`Creds::read`]. The hashes are stored as they are, and
+    /// [`Creds::verify`] checks passwords against them; see
+    /// [`htpasswd`] for the supported hash schemes.
+    pub fn read_htpasswd(reader: impl BufRead) -> Result<Self, CredsError> {
+        Self::read(reader)
+    }
+
+    /// Parses a `/etc/shadow`-style file from the given reader.
+    ///
+    /// Each line is a colon-separated record like
+    /// `<username>:<hash>:<lastchg>:<min>:<max>:...`. The username and hash
+    /// are taken from the first two fields, and the other fields are ignored.
+    /// If `skip_locked` is `true`, accounts whose hash starts with `!` or `*`,
+    /// which marks them as locked or without a password, are left out.
+    ///
+    /// The resulting store holds the hashes, not plaintext passwords.
+    /// [`Creds::verify`] only checks the hash schemes listed in [`hash`] and
+    /// [`htpasswd`], such as bcrypt. Locked accounts that are kept, and
+    /// accounts with hashes that cannot be checked, like SHA-512-crypt
+    /// (`$6$`), are kept with their hash but [expire](Creds::insert_with_expiry)
+    /// at the Unix epoch, so that no password is ever accepted for them,
+    /// including the hash itself. An empty hash field is stored as an empty
+    /// password, which matches the empty password, as it does for the system.
+    pub fn read_shadow(reader: impl BufRead, skip_locked: bool) -> Result<Self, CredsError> {
+        let mut creds = Self::read(reader)?;
+        if skip_locked {
+            creds
+                .map
+                .retain(|_, stored| !stored.starts_with(['!', '*']));
+        }
+        for stored in creds.map.values_mut() {
+            if let Some(end) = stored.find(':') {
+                stored.truncate(end);
+            }
+            if !stored.is_empty() && hasher::verify("", stored).is_none() {
+                stored.meta.set_expires(Some(SystemTime::UNIX_EPOCH));
+            }
+        }
+        Ok(creds)
+    }
+
+    /// Parses credentials from the given reader, detecting their format.
+    ///
+    /// See [`FileFormat::detect`] for how the format is recognized. The line
+    /// format, including `.htpasswd` files, is read with [`Creds::read`], and
+    /// JSON with `Creds::read_json`, which requires the `json` feature. Returns
+    /// [`CredsError::UnrecognizedFormat`] if the format cannot be determined,
+    /// or if it is JSON and the `json` feature is disabled.
+    pub fn read_auto(mut reader: impl Read) -> Result<Self, CredsError> {
+        let mut contents = Zeroizing::new(Vec::new());
+        reader.read_to_end(&mut contents)?;
+        match FileFormat::detect(&contents) {
+            Some(FileFormat::Lines) => Self::read(&contents[..]),
+            #[cfg(feature = "json")]
+            Some(FileFormat::Json) => Self::read_json(&contents[..]),
+            #[cfg(not(feature = "json"))]
+            Some(FileFormat::Json) => Err(CredsError::UnrecognizedFormat),
+            None => Err(CredsError::UnrecognizedFormat),
+        }
+    }
+
+    /// Writes the credentials into the given writer as an Apache `.htpasswd`
+    /// file.
+    ///
+    /// Entries that already use an `.htpasswd` hash scheme are written as they
+    /// are. Plaintext passwords are hashed with bcrypt at
+    /// [`DEFAULT_BCRYPT_COST`](htpasswd::DEFAULT_BCRYPT_COST). Argon2 and scrypt
+    /// hashes are not understood by Apache, so they cause a
+    /// [`CredsError::UnsupportedHash`] error.
+    ///
+    /// See [`Creds::write_htpasswd_bcrypt`] to choose the cost.
+    pub fn write_htpasswd(&self, writer: impl Write) -> Result<(), CredsError> {
+        self.write_htpasswd_bcrypt(writer, htpasswd::DEFAULT_BCRYPT_COST)
+    }
+
+    /// Writes the credentials into the given writer as an Apache `.htpasswd`
+    /// file, hashing plaintext passwords with bcrypt at the given cost.
+    ///
+    /// The hashes use the `$2y$` prefix, which Apache and nginx both accept.
+    /// Otherwise, this is the same as [`Creds::write_htpasswd`].
+    ///
+    /// Returns [`CredsError::InvalidBcryptCost`] if the cost is outside of the
+    /// range bcrypt accepts, 4 to 31, before writing anything.
+    pub fn write_htpasswd_bcrypt(&self, writer: impl Write, cost: u32) -> Result<(), CredsError> {
+        let cost = htpasswd::check_bcrypt_cost(cost)
+            .map_err(|_| CredsError::InvalidBcryptCost { cost })?;
+        let mut hashed = Self::new();
+        for (username, password) in self {
+            if htpasswd::is_htpasswd_hash(password) {
+                hashed.insert(username, password);
+            } else if hasher::is_recognized(password) {
+                return Err(CredsError::UnsupportedHash {
+                    username: username.to_owned(),
+                });
+            } else {
+                let hash = htpasswd::hash_bcrypt(password, cost)
+                    .map_err(|_| CredsError::InvalidBcryptCost { cost })?;
+                hashed.insert(username, hash);
+            }
+        }
+        hashed.write(writer)
+    }
+}
+
+/// How [`Creds::merge_with`] handles a username that exists in both stores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MergePolicy {
+    /// Keep the password already in the store.
+    KeepExisting,
+    /// Replace the password with the one from the merged store.
+    Overwrite,
+    /// Fail with a [`MergeConflict`], without changing the store.
+    Error,
+}
+
+/// The differences between two credential stores, as returned by
+/// [`Creds::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CredsDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>,
+}
+
+impl CredsDiff {
+    /// Returns the usernames that are only in the other store, in its
+    /// insertion order.
+    pub fn added(&self) -> &[String] {
+        &self.added
+    }
+
+    /// Returns the usernames that are only in this store, in its insertion
+    /// order.
+    pub fn removed(&self) -> &[String] {
+        &self.removed
+    }
+
+    /// Returns the usernames that are in both stores with different
+    /// passwords, in this store's insertion order.
+    pub fn changed(&self) -> &[String] {
+        &self.changed
+    }
+
+    /// Returns `true` if the stores contain the same credentials.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The error type for reading and writing credential stores.
+#[derive(Debug)]
+pub enum CredsError {
+    /// An I/O error from the underlying reader, writer, or file.
+    Io(io::Error),
+    /// A credential could not be written because its username or password
+    /// contains characters that the format cannot represent.
+    IllegalCharacter {
+        /// The username of the offending credential.
+        username: String,
+    },
+    /// A line could not be parsed as a credential.
+    ///
+    /// The contents of the line are not included, since they may well be a
+    /// password.
+    MalformedLine {
+        /// The 1-based number of the offending line.
+        line_number: usize,
+    },
+    /// A credential could not be written because its password is a hash that
+    /// the output format does not support.
+    UnsupportedHash {
+        /// The username of the offending credential.
+        username: String,
+    },
+    /// A store could not be migrated to strong hashes because a password is
+    /// a hash that is too weak to keep, and cannot be hashed again without
+    /// the password.
+    WeakHash {
+        /// The username of the offending credential.
+        username: String,
+    },
+    /// A bcrypt cost is outside of the range bcrypt accepts, 4 to 31.
+    InvalidBcryptCost {
+        /// The rejected cost.
+        cost: u32,
+    },
+    /// An encrypted credential file could not be decrypted, because the
+    /// passphrase is wrong or the file is not an encrypted credential file or
+    /// has been modified.
+    Decryption,
+    /// A credential file failed its integrity check, because the key is wrong
+    /// or the file has no integrity footer or has been modified.
+    Integrity,
+    /// The checksum line at the end of a credential file does not match its
+    /// contents, because the file has been truncated or corrupted.
+    ChecksumMismatch,
+    /// The format of a credential file could not be
+    /// [detected](FileFormat::detect).
+    UnrecognizedFormat,
+    /// A username appears on more than one line, and the reader was set to
+    /// [reject duplicates](DuplicatePolicy::Error).
+    DuplicateUsername {
+        /// The 1-based number of the line repeating the username.
+        line_number: usize,
+        /// The repeated username.
+        username: String,
+    },
+    /// A line is longer than the [maximum line
+    /// length](crate::format::ReadOptions::max_line_len).
+    LineTooLong {
+        /// The 1-based number of the offending line.
+        line_number: usize,
+        /// The maximum line length, in bytes.
+        max_len: usize,
+    },
+    /// A line is not valid UTF-8.
+    InvalidUtf8 {
+        /// The 1-based number of the offending line.
+        line_number: usize,
+        /// The offset of the first invalid byte from the start of the input,
+        /// in bytes.
+        offset: usize,
+    },
+    /// An I/O error occurred while reading a specific line.
+    ReadLine {
+        /// The 1-based number of the line being read.
+        line_number: usize,
+        /// The underlying error.
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for CredsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => error.fmt(f),
+            Self::IllegalCharacter { username } => write!(
+                f,
+                "credential for username {:?} contains illegal characters",
+                username
+            ),
+            Self::MalformedLine { line_number } => {
+                write!(f, "line {} is not a valid credential", line_number)
+            }
+            Self::UnsupportedHash { username } => write!(
+                f,
+                "password hash for username {:?} is not supported by this format",
+                username
+            ),
+            Self::WeakHash { username } => write!(
+                f,
+                "password hash for username {:?} is too weak to keep",
+                username
+            ),
+            Self::InvalidBcryptCost { cost } => {
+                write!(f, "bcrypt cost {} is not between 4 and 31", cost)
+            }
+            Self::Decryption => f.write_str(
+                "decryption failed: wrong passphrase, or not a valid encrypted credential file",
+            ),
+            Self::Integrity => f.write_str(
+                "integrity check failed: wrong key, or the file is missing its footer or has been modified",
+            ),
+            Self::ChecksumMismatch => {
+                f.write_str("checksum does not match the contents of the file")
+            }
+            Self::UnrecognizedFormat => f.write_str("unrecognized credential file format"),
+            Self::DuplicateUsername {
+                line_number,
+                username,
+            } => write!(f, "line {} repeats username {:?}", line_number, username),
+            Self::LineTooLong {
+                line_number,
+                max_len,
+            } => write!(
+                f,
+                "line {} is longer than the maximum of {} bytes",
+                line_number, max_len
+            ),
+            Self::InvalidUtf8 {
+                line_number,
+                offset,
+            } => write!(
+                f,
+                "line {} is not valid UTF-8, at byte offset {}",
+                line_number, offset
+            ),
+            Self::ReadLine {
+                line_number,
+                source,
+            } => write!(f, "failed to read line {}: {}", line_number, source),
+        }
+    }
+}
+
+impl Error for CredsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::ReadLine { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CredsError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<CredsError> for io::Error {
+    /// Converts the error back into an [`io::Error`], using
+    /// [`InvalidData`](io::ErrorKind::InvalidData) for format errors.
+    fn from(error: CredsError) -> Self {
+        match error {
+            CredsError::Io(error) => error,
+            CredsError::ReadLine { ref source, .. } => io::Error::new(source.kind(), error),
+            other => io::Error::new(io::ErrorKind::InvalidData, other),
+        }
+    }
+}
+
+/// The error type for [`Creds::rename`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenameError {
+    /// The username to rename does not exist in the store.
+    NotFound {
+        /// The username that was not found.
+        username: String,
+    },
+    /// The new username already belongs to another credential.
+    AlreadyExists {
+        /// The username that already exists.
+        username: String,
+    },
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { username } => write!(f, "username {:?} does not exist", username),
+            Self::AlreadyExists { username } => {
+                write!(f, "username {:?} already exists", username)
+            }
+        }
+    }
+}
+
+impl Error for RenameError {}
+
+/// The error type for [`Creds::change_password`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeError {
+    /// The username does not exist in the store.
+    UserNotFound {
+        /// The username that was not found.
+        username: String,
+    },
+    /// The given current password does not match the stored one.
+    WrongPassword {
+        /// The username whose password did not match.
+        username: String,
+    },
+    /// The given current password matches, but the credential has
+    /// [expired](Creds::insert_with_expiry).
+    Expired {
+        /// The username whose credential has expired.
+        username: String,
+    },
+}
+
+impl fmt::Display for ChangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UserNotFound { username } => write!(f, "username {:?} does not exist", username),
+            Self::WrongPassword { username } => {
+                write!(f, "wrong current password for username {:?}", username)
+            }
+            Self::Expired { username } => {
+                write!(f, "credential for username {:?} has expired", username)
+            }
+        }
+    }
+}
+
+impl Error for ChangeError {}
+
+/// The error type for [`Creds::merge_with`] with [`MergePolicy::Error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    username: String,
+}
+
+impl MergeConflict {
+    /// Returns the username that exists in both stores.
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "username {:?} exists in both stores", self.username)
+    }
+}
+
+impl Error for MergeConflict {}
+
+/// A username that was changed differently on both sides of
+/// [`Creds::merge3`].
+///
+/// Each password is `None` if the username does not exist in that store. The
+/// [`Debug`](fmt::Debug) output redacts the passwords.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Conflict {
+    username: String,
+    base: Option<String>,
+    ours: Option<String>,
+    theirs: Option<String>,
+}
+
+impl Conflict {
+    /// Returns the conflicting username.
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Returns the password in the common base store.
+    pub fn base(&self) -> Option<&str> {
+        self.base.as_deref()
+    }
+
+    /// Returns the password in `ours`.
+    pub fn ours(&self) -> Option<&str> {
+        self.ours.as_deref()
+    }
+
+    /// Returns the password in `theirs`.
+    pub fn theirs(&self) -> Option<&str> {
+        self.theirs.as_deref()
+    }
+}
+
+impl fmt::Debug for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redact = |password: &Option<String>| password.as_ref().map(|_| Redacted);
+        f.debug_struct("Conflict")
+            .field("username", &self.username)
+            .field("base", &redact(&self.base))
+            .field("ours", &redact(&self.ours))
+            .field("theirs", &redact(&self.theirs))
+            .finish()
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "username {:?} was changed differently on both sides",
+            self.username
+        )
+    }
+}
+
+impl Error for Conflict {}
+
+/// The error type for [`Creds::try_insert`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidCredential {
+    /// The username is empty.
+    EmptyUsername,
+    /// The username contains the delimiter or a line break, or starts with the
+    /// comment character.
+    IllegalUsername {
+        /// The offending username.
+        username: String,
+        /// The delimiter of the store.
+        delimiter: char,
+        /// The character that starts comment lines.
+        comment: char,
+    },
+    /// The password contains a line break.
+    IllegalPassword {
+        /// The username the password was for.
+        username: String,
+    },
+}
+
+impl fmt::Display for InvalidCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyUsername => f.write_str("username is empty"),
+            Self::IllegalUsername {
+                username,
+                delimiter,
+                comment,
+            } => write!(
+                f,
+                "username {:?} contains {:?} or a line break, or starts with {:?}",
+                username, delimiter, comment
+            ),
+            Self::IllegalPassword { username } => {
+                write!(
+                    f,
+                    "password for username {:?} contains a line break",
+                    username
+                )
+            }
+        }
+    }
+}
+
+impl Error for InvalidCredential {}
+
+/// Compares a stored secret against a candidate without short-circuiting.
+///
+/// Unlike the slice comparison in [`subtle`], this does not return early when
+/// the lengths differ; it always does one step of work per byte of
+/// `candidate`, so the running time does not reveal the length of `stored` or
+/// how many leading bytes matched.
+pub(crate) fn constant_time_eq(stored: &[u8], candidate: &[u8]) -> Choice {
+    let mut equal = (stored.len() as u64).ct_eq(&(candidate.len() as u64));
+    for (index, byte) in candidate.iter().enumerate() {
+        let stored_byte = stored.get(index).copied().unwrap_or(0);
+        equal &= stored_byte.ct_eq(byte);
+    }
+    equal
+}
+
+/// Stands in for a password in [`Debug`](fmt::Debug) output.
+pub(crate) struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"********\"")
+    }
+}
+
+impl fmt::Debug for Creds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Credentials<'a>(&'a Creds);
+
+        impl fmt::Debug for Credentials<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_map()
+                    .entries(self.0.usernames().map(|username| (username, Redacted)))
+                    .finish()
+            }
+        }
+
+        f.debug_struct("Creds")
+            .field("credentials", &Credentials(self))
+            .field("hash_params", &self.hash_params)
+            .finish()
+    }
+}
+
+impl fmt::Display for Creds {
+    /// Formats the store in the format of a credentials file, like
+    /// [`Creds::to_string_contents`].
+    ///
+    /// Unlike [`Creds::write`], this cannot fail, so credentials with illegal
+    /// characters are written as they are, and may not read back the same.
+    /// Note that this includes the passwords, unlike the
+    /// [`Debug`](fmt::Debug) output.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (username, password) in self {
+            writeln!(f, "{}{}{}", username, self.delimiter, password)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Creds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for Creds {
+    /// Two stores are equal if they contain the same usernames, spelled the
+    /// same, with the same passwords, regardless of insertion order. Settings
+    /// like the hash parameters, case sensitivity, and delimiter of the stores
+    /// are not compared.
+    ///
+    /// Passwords are compared in constant time, and every entry is compared
+    /// even after a mismatch is found, so the running time does not reveal
+    /// which or how many passwords differ.
+    fn eq(&self, other: &Self) -> bool {
+        let mut equal = (self.len() as u64).ct_eq(&(other.len() as u64));
+        for (username, password) in self {
+            let (found, stored) = match other.map.get_key_value(&other.key(username)) {
+                Some((stored_username, stored)) if stored_username.name == username => {
+                    (Choice::from(1), stored.as_str())
+                }
+                _ => (Choice::from(0), password),
+            };
+            equal &= found & constant_time_eq(stored.as_bytes(), password.as_bytes());
+        }
+        equal.into()
+    }
+}
+
+impl Eq for Creds {}
+
+impl FromStr for Creds {
+    type Err = CredsError;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        Self::from_str_contents(contents)
+    }
+}
+
+impl<S> Index<S> for Creds
+where
+    S: AsRef<str>,
 {
     type Output = str;
 
@@ -116,8 +1867,1408 @@ where
         let username = username.as_ref();
 
         match self.get(username) {
-            Some(string) => &*string,
+            Some(string) => string,
             None => panic!("username not present: {}", username),
         }
     }
 }
+
+/// A view into a single username's entry in a [`Creds`] store, which may
+/// either be vacant or occupied.
+///
+/// Created by [`Creds::entry`].
+#[derive(Debug)]
+pub enum Entry<'a> {
+    /// The username has a stored password.
+    Occupied(OccupiedEntry<'a>),
+    /// The username has no stored password.
+    Vacant(VacantEntry<'a>),
+}
+
+impl<'a> Entry<'a> {
+    /// Returns the username of this entry.
+    pub fn username(&self) -> &str {
+        match self {
+            Self::Occupied(entry) => entry.username(),
+            Self::Vacant(entry) => entry.username(),
+        }
+    }
+
+    /// Inserts the given password if the entry is vacant, and returns a
+    /// mutable reference to the stored password.
+    pub fn or_insert(self, password: impl Into<String>) -> &'a mut String {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => entry.insert(password),
+        }
+    }
+
+    /// Inserts the result of `default` if the entry is vacant, and returns a
+    /// mutable reference to the stored password.
+    ///
+    /// `default` is only called if the entry is vacant.
+    pub fn or_insert_with<F, P>(self, default: F) -> &'a mut String
+    where
+        F: FnOnce() -> P,
+        P: Into<String>,
+    {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Modifies the stored password in place if the entry is occupied.
+    ///
+    /// This records the password as [modified](CredMeta::modified) now, like
+    /// [`OccupiedEntry::get_mut`].
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut String),
+    {
+        if let Self::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+/// A view into an occupied entry in a [`Creds`] store.
+pub struct OccupiedEntry<'a> {
+    inner: indexmap::map::OccupiedEntry<'a, Username, Password>,
+}
+
+impl<'a> OccupiedEntry<'a> {
+    /// Returns the username of this entry.
+    pub fn username(&self) -> &str {
+        &self.inner.key().name
+    }
+
+    /// Returns the stored password.
+    pub fn get(&self) -> &str {
+        self.inner.get()
+    }
+
+    /// Returns a mutable reference to the stored password.
+    ///
+    /// Since the password may be changed through the reference, this records
+    /// it as [modified](CredMeta::modified) now, like
+    /// [`OccupiedEntry::insert`].
+    pub fn get_mut(&mut self) -> &mut String {
+        let stored = self.inner.get_mut();
+        stored.meta.touch(SystemTime::now());
+        &mut stored.secret
+    }
+
+    /// Converts the entry into a mutable reference to the stored password,
+    /// with the lifetime of the store.
+    pub fn into_mut(self) -> &'a mut String {
+        self.inner.into_mut()
+    }
+
+    /// Replaces the stored password, returning the old one.
+    ///
+    /// The entry keeps its position in the store.
+    pub fn insert(&mut self, password: impl Into<String>) -> String {
+        let mut meta = self.inner.get().meta.clone();
+        meta.touch(SystemTime::now());
+        let secret = Zeroizing::new(password.into());
+        into_plain(self.inner.insert(Password { secret, meta }))
+    }
+
+    /// Removes the entry from the store, returning its password.
+    ///
+    /// Like [`Creds::remove`], this preserves the insertion order of the
+    /// remaining entries.
+    pub fn remove(self) -> String {
+        into_plain(self.inner.shift_remove())
+    }
+}
+
+impl fmt::Debug for OccupiedEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedEntry")
+            .field("username", &self.username())
+            .field("password", &Redacted)
+            .finish()
+    }
+}
+
+/// A view into a vacant entry in a [`Creds`] store.
+#[derive(Debug)]
+pub struct VacantEntry<'a> {
+    inner: indexmap::map::VacantEntry<'a, Username, Password>,
+}
+
+impl<'a> VacantEntry<'a> {
+    /// Returns the username of this entry.
+    pub fn username(&self) -> &str {
+        &self.inner.key().name
+    }
+
+    /// Stores a password for this username at the end of the store, and
+    /// returns a mutable reference to it.
+    pub fn insert(self, password: impl Into<String>) -> &'a mut String {
+        self.inner.insert(Password {
+            secret: Zeroizing::new(password.into()),
+            meta: CredMeta::new_at(SystemTime::now()),
+        })
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for Creds
+where
+    K: Into<String>,
+    V: Into<String>,
+{
+    /// Collects username/password pairs into a new store.
+    ///
+    /// As with [`Creds::insert`], a later pair overwrites the password of an
+    /// earlier pair with the same username.
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut creds = Self::new();
+        creds.extend(iter);
+        creds
+    }
+}
+
+impl<K, V> Extend<(K, V)> for Creds
+where
+    K: Into<String>,
+    V: Into<String>,
+{
+    /// Inserts every username/password pair from the iterator.
+    ///
+    /// As with [`Creds::insert`], existing passwords are overwritten.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (username, password) in iter {
+            self.insert(username, password);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Creds {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for Creds {
+    type Item = (String, String);
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            inner: self.map.into_iter(),
+        }
+    }
+}
+
+/// An iterator over the username/password pairs of a [`Creds`] store.
+///
+/// Created by [`Creds::iter`].
+#[derive(Clone)]
+pub struct Iter<'a> {
+    inner: indexmap::map::Iter<'a, Username, Password>,
+}
+
+impl fmt::Debug for Iter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.clone().map(|(username, _)| (username, Redacted)))
+            .finish()
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|(username, password)| (username.name.as_str(), password.as_str()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next_back()
+            .map(|(username, password)| (username.name.as_str(), password.as_str()))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+/// An iterator over the usernames of a [`Creds`] store.
+///
+/// Created by [`Creds::usernames`].
+#[derive(Debug, Clone)]
+pub struct Usernames<'a> {
+    inner: indexmap::map::Keys<'a, Username, Password>,
+}
+
+impl<'a> Iterator for Usernames<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|username| username.name.as_str())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Usernames<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next_back()
+            .map(|username| username.name.as_str())
+    }
+}
+
+impl ExactSizeIterator for Usernames<'_> {}
+
+/// An owning iterator over the username/password pairs of a [`Creds`] store.
+///
+/// Created by the [`IntoIterator`] implementation for [`Creds`].
+pub struct IntoIter {
+    inner: indexmap::map::IntoIter<Username, Password>,
+}
+
+impl fmt::Debug for IntoIter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntoIter")
+            .field("remaining", &self.inner.len())
+            .finish()
+    }
+}
+
+impl Iterator for IntoIter {
+    type Item = (String, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|(username, password)| (username.name, into_plain(password)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for IntoIter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next_back()
+            .map(|(username, password)| (username.name, into_plain(password)))
+    }
+}
+
+impl ExactSizeIterator for IntoIter {}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use super::*;
+    use crate::file::TestDir;
+
+    fn creds(pairs: &[(&str, &str)]) -> Creds {
+        pairs.iter().copied().collect()
+    }
+
+    /// Returns what [`Creds::write`] writes for the store.
+    fn written(creds: &Creds) -> String {
+        let mut contents = Vec::new();
+        creds.write(&mut contents).unwrap();
+        String::from_utf8(contents).unwrap()
+    }
+
+    #[test]
+    fn remove_keeps_the_order_of_other_entries() {
+        let mut store = Creds::new();
+        store.insert("alice", "a");
+        store.insert("bob", "b");
+        store.insert("carol", "c");
+        assert_eq!(store.remove("bob"), Some("b".to_owned()));
+        assert_eq!(store.remove("bob"), None);
+
+        let mut written = Vec::new();
+        store.write(&mut written).unwrap();
+        assert_eq!(written, b"alice:a\ncarol:c\n");
+    }
+
+    #[test]
+    fn len_counts_overwritten_entries_once() {
+        let mut store = Creds::new();
+        assert!(store.is_empty());
+        store.insert("alice", "a");
+        store.insert("bob", "b");
+        store.insert("carol", "c");
+        store.insert("bob", "b2");
+        assert_eq!(store.len(), 3);
+        assert!(!store.is_empty());
+    }
+
+    #[test]
+    fn collect_keeps_the_first_position_and_last_password() {
+        let store: Creds = vec![("alice", "a"), ("bob", "b"), ("alice", "a2")]
+            .into_iter()
+            .collect();
+        assert_eq!(written(&store), "alice:a2\nbob:b\n");
+    }
+
+    #[test]
+    fn extend_appends_new_usernames_and_overwrites_existing_ones() {
+        let mut store = creds(&[("alice", "a"), ("bob", "b")]);
+        store.extend(vec![
+            ("carol".to_owned(), "c".to_owned()),
+            ("alice".to_owned(), "a2".to_owned()),
+        ]);
+        assert_eq!(written(&store), "alice:a2\nbob:b\ncarol:c\n");
+    }
+
+    #[test]
+    fn verify_accepts_only_the_stored_password() {
+        let store = creds(&[("alice", "secret"), ("bob", "")]);
+        assert!(store.verify("alice", "secret"));
+        assert!(!store.verify("alice", "secreT"));
+        assert!(!store.verify("alice", "secret2"));
+        assert!(!store.verify("alice", "secre"));
+        assert!(!store.verify("alice", ""));
+        assert!(store.verify("bob", ""));
+        assert!(!store.verify("carol", "secret"));
+        assert!(!store.verify("carol", ""));
+    }
+
+    #[test]
+    fn read_strict_reports_the_malformed_line_number() {
+        let error = Creds::read_strict("alice:a\nmalformed\nbob:b\n".as_bytes()).unwrap_err();
+        assert!(matches!(
+            error,
+            CredsError::MalformedLine { line_number: 2 }
+        ));
+        assert!(error.to_string().contains("line 2"), "{}", error);
+
+        let lenient = Creds::read("alice:a\nmalformed\nbob:b\n".as_bytes()).unwrap();
+        assert_eq!(lenient.len(), 2);
+    }
+
+    #[test]
+    fn malformed_line_errors_do_not_show_the_line() {
+        let error = Creds::read_strict("alice=hunter2\n".as_bytes()).unwrap_err();
+        for shown in [error.to_string(), format!("{:?}", error)] {
+            assert!(shown.contains('1'), "{}", shown);
+            assert!(!shown.contains("hunter2"), "{}", shown);
+        }
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let contents = "# users\nalice:a\n\n   # indented comment\nbob:b\n\t\n";
+        let creds = Creds::read_strict(contents.as_bytes()).unwrap();
+        assert_eq!(written(&creds), "alice:a\nbob:b\n");
+
+        let error = Creds::read_strict("# users\nalice=a\n".as_bytes()).unwrap_err();
+        assert!(matches!(
+            error,
+            CredsError::MalformedLine { line_number: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn clear_and_remove_discard_entries() {
+        let mut store = creds(&[("alice", "a"), ("bob", "b")]);
+        store.insert("alice", "a2");
+        assert_eq!(store.get("alice"), Some("a2"));
+        assert_eq!(store.remove("alice"), Some("a2".to_owned()));
+        assert!(!store.verify("alice", "a2"));
+        store.clear();
+        assert!(store.is_empty());
+        assert_eq!(store.get("bob"), None);
+        store.insert("bob", "b2");
+        assert!(store.verify("bob", "b2"));
+    }
+
+    #[test]
+    fn debug_shows_usernames_but_no_passwords() {
+        let store = creds(&[("alice", "hunter2"), ("bob", "correct horse")]);
+        let debug = format!("{:?}", store);
+        assert!(debug.contains("\"alice\": \"********\""), "{}", debug);
+        assert!(debug.contains("bob"), "{}", debug);
+        assert!(!debug.contains("hunter2"), "{}", debug);
+        assert!(!debug.contains("correct horse"), "{}", debug);
+    }
+
+    #[test]
+    fn string_contents_round_trip() {
+        let store = creds(&[("alice", "a"), ("bob", "b:c")]);
+        let contents = store.to_string_contents().unwrap();
+        assert_eq!(contents, "alice:a\nbob:b:c\n");
+        assert_eq!(
+            written(&Creds::from_str_contents(&contents).unwrap()),
+            contents
+        );
+        assert_eq!(written(&contents.parse::<Creds>().unwrap()), contents);
+    }
+
+    #[test]
+    fn entry_only_calls_or_insert_with_for_new_usernames() {
+        let mut store = creds(&[("alice", "a")]);
+        let password = store
+            .entry("alice")
+            .or_insert_with(|| -> String { panic!("alice already has a password") });
+        assert_eq!(password, "a");
+
+        let mut calls = 0;
+        let password = store.entry("bob").or_insert_with(|| {
+            calls += 1;
+            "generated".to_owned()
+        });
+        assert_eq!(password, "generated");
+        assert_eq!(calls, 1);
+        assert_eq!(store.entry("carol").or_insert("c"), "c");
+        assert_eq!(
+            store.to_string_contents().unwrap(),
+            "alice:a\nbob:generated\ncarol:c\n"
+        );
+    }
+
+    #[test]
+    fn case_insensitive_lookups_fold_unicode() {
+        let mut store = Creds::new_case_insensitive();
+        store.insert("Héllo", "secret");
+        assert_eq!(store.get("héllo"), Some("secret"));
+        assert_eq!(store.get("HÉLLO"), Some("secret"));
+        assert!(store.verify("hÉllo", "secret"));
+        store.insert("héllo", "new");
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.to_string_contents().unwrap(), "Héllo:new\n");
+
+        let mut exact = creds(&[("Héllo", "a")]);
+        assert_eq!(exact.get("héllo"), None);
+        exact.insert("héllo", "b");
+        exact.set_case_insensitive(true);
+        assert_eq!(exact.len(), 1);
+        assert_eq!(exact.to_string_contents().unwrap(), "Héllo:b\n");
+    }
+
+    #[test]
+    fn debug_of_views_redacts_passwords() {
+        let mut store = creds(&[("alice", "hunter2")]);
+        let outputs = [
+            format!("{:?}", store.iter()),
+            format!("{:?}", crate::format::Line::parse("alice:hunter2")),
+            format!("{:?}", hash::HashedCreds::from(store.clone())),
+            format!("{:?}", store.entry("alice")),
+        ];
+        for debug in outputs {
+            assert!(debug.contains("alice"), "{}", debug);
+            assert!(!debug.contains("hunter2"), "{}", debug);
+        }
+        let debug = format!("{:?}", store.into_iter());
+        assert!(!debug.contains("hunter2"), "{}", debug);
+    }
+
+    #[test]
+    fn merge_overwrites_and_appends_new_usernames() {
+        let mut store = creds(&[("alice", "a"), ("bob", "b")]);
+        store.merge(creds(&[("carol", "c"), ("alice", "a2")]));
+        assert_eq!(
+            store.to_string_contents().unwrap(),
+            "alice:a2\nbob:b\ncarol:c\n"
+        );
+        store.merge_ref(&creds(&[("dave", "d")]));
+        assert_eq!(store.get("dave"), Some("d"));
+    }
+
+    #[test]
+    fn merge_with_follows_the_policy() {
+        let base = creds(&[("alice", "a"), ("bob", "b")]);
+        let other = creds(&[("carol", "c"), ("alice", "a2")]);
+
+        let mut kept = base.clone();
+        kept.merge_with(other.clone(), MergePolicy::KeepExisting)
+            .unwrap();
+        assert_eq!(
+            kept.to_string_contents().unwrap(),
+            "alice:a\nbob:b\ncarol:c\n"
+        );
+
+        let mut overwritten = base.clone();
+        overwritten
+            .merge_with(other, MergePolicy::Overwrite)
+            .unwrap();
+        assert_eq!(
+            overwritten.to_string_contents().unwrap(),
+            "alice:a2\nbob:b\ncarol:c\n"
+        );
+    }
+
+    #[test]
+    fn collect_with_duplicates_keeps_the_last_password() {
+        let pairs = vec![
+            ("alice".to_owned(), "first".to_owned()),
+            ("bob".to_owned(), "b".to_owned()),
+            ("alice".to_owned(), "last".to_owned()),
+        ];
+        let store: Creds = pairs.into_iter().collect();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get("alice"), Some("last"));
+    }
+
+    #[test]
+    fn rename_keeps_the_password_and_position() {
+        let mut store = creds(&[("alice", "a"), ("bob", "b"), ("carol", "c")]);
+        store.rename("bob", "robert").unwrap();
+        assert_eq!(store.get("bob"), None);
+        assert_eq!(
+            store.to_string_contents().unwrap(),
+            "alice:a\nrobert:b\ncarol:c\n"
+        );
+
+        assert_eq!(
+            store.rename("bob", "bobby"),
+            Err(RenameError::NotFound {
+                username: "bob".to_owned()
+            })
+        );
+        assert_eq!(
+            store.rename("alice", "carol"),
+            Err(RenameError::AlreadyExists {
+                username: "carol".to_owned()
+            })
+        );
+        assert_eq!(store.get("alice"), Some("a"));
+        assert_eq!(store.get("carol"), Some("c"));
+    }
+
+    #[test]
+    fn append_to_file_lets_the_latest_value_win() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        creds(&[("alice", "a"), ("bob", "b")])
+            .append_to_file(&path)
+            .unwrap();
+        creds(&[("alice", "a2")]).append_to_file(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "alice:a\nbob:b\nalice:a2\n"
+        );
+        let read = Creds::read_from_file(&path).unwrap();
+        assert_eq!(read.get("alice"), Some("a2"));
+        assert_eq!(read.get("bob"), Some("b"));
+    }
+
+    #[test]
+    fn merge_with_error_reports_the_conflicting_username() {
+        let base = creds(&[("alice", "a"), ("bob", "b")]);
+        let other = creds(&[("carol", "c"), ("alice", "a2")]);
+        let mut failed = base.clone();
+        let conflict = failed.merge_with(other, MergePolicy::Error).unwrap_err();
+        assert_eq!(conflict.username(), "alice");
+        assert_eq!(written(&failed), written(&base));
+        assert_eq!(failed.get("carol"), None);
+    }
+
+    #[test]
+    fn retain_keeps_matching_entries_in_order() {
+        let mut store = creds(&[
+            ("admin-alice", "a"),
+            ("bob", "b"),
+            ("admin-carol", "c"),
+            ("dave-admin", "d"),
+        ]);
+        store.retain(|username, _| username.starts_with("admin"));
+        assert_eq!(
+            store.to_string_contents().unwrap(),
+            "admin-alice:a\nadmin-carol:c\n"
+        );
+        store.retain(|_, password| password != "c");
+        assert_eq!(store.usernames().collect::<Vec<_>>(), ["admin-alice"]);
+    }
+
+    #[test]
+    fn tab_and_pipe_delimited_round_trips() {
+        let store = creds(&[("alice", "a:b"), ("bob", "c")]);
+        for (delimiter, contents) in [('\t', "alice\ta:b\nbob\tc\n"), ('|', "alice|a:b\nbob|c\n")] {
+            let mut out = Vec::new();
+            store.write_with_delimiter(&mut out, delimiter).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), contents);
+            let read = Creds::read_with_delimiter(contents.as_bytes(), delimiter).unwrap();
+            assert_eq!(read, store);
+        }
+    }
+
+    #[test]
+    fn writing_rejects_usernames_containing_the_delimiter() {
+        let store = creds(&[("a|b", "c")]);
+        assert!(matches!(
+            store.write_with_delimiter(Vec::new(), '|'),
+            Err(CredsError::IllegalCharacter { username }) if username == "a|b"
+        ));
+        assert!(store.write_with_delimiter(Vec::new(), '\t').is_ok());
+    }
+
+    #[test]
+    fn case_insensitive_stores_keep_the_original_casing() {
+        let mut store = Creds::new_case_insensitive();
+        store.insert("Alice", "a");
+        store.insert("ÅSA", "b");
+        assert!(store.verify("ALICE", "a"));
+        assert!(store.verify("åsa", "b"));
+        assert!(!store.verify("asa", "b"));
+
+        let contents = store.to_string_contents().unwrap();
+        assert_eq!(contents, "Alice:a\nÅSA:b\n");
+        let mut read = Creds::read(contents.as_bytes()).unwrap();
+        read.set_case_insensitive(true);
+        assert_eq!(read.get("alice"), Some("a"));
+        assert_eq!(read.remove("Åsa"), Some("b".to_owned()));
+        assert_eq!(read.usernames().collect::<Vec<_>>(), ["Alice"]);
+    }
+
+    #[test]
+    fn get_mut_edits_the_stored_password() {
+        let mut store = creds(&[("alice", "old")]);
+        store.get_mut("alice").unwrap().push_str("-rotated");
+        assert!(store.verify("alice", "old-rotated"));
+        assert!(!store.verify("alice", "old"));
+        assert!(store.get_mut("bob").is_none());
+    }
+
+    #[test]
+    fn diff_reports_additions_removals_and_changes() {
+        let deployed = creds(&[("alice", "a"), ("bob", "b"), ("carol", "c")]);
+        let desired = creds(&[("alice", "a"), ("carol", "c2"), ("dave", "d")]);
+        let diff = deployed.diff(&desired);
+        assert_eq!(diff.added(), ["dave"]);
+        assert_eq!(diff.removed(), ["bob"]);
+        assert_eq!(diff.changed(), ["carol"]);
+        assert!(!diff.is_empty());
+        let debug = format!("{:?}", diff);
+        assert!(!debug.contains("c2"), "{}", debug);
+        assert!(deployed.diff(&deployed).is_empty());
+    }
+
+    #[test]
+    fn crlf_files_read_like_lf_files() {
+        let store = Creds::read("user:pass\r\nother:pw\r\n".as_bytes()).unwrap();
+        assert_eq!(store.get("user"), Some("pass"));
+        assert!(store.verify("user", "pass"));
+        assert_eq!(written(&store), "user:pass\nother:pw\n");
+    }
+
+    #[test]
+    fn equality_ignores_insertion_order() {
+        let forward = creds(&[("alice", "a"), ("bob", "b")]);
+        let backward = creds(&[("bob", "b"), ("alice", "a")]);
+        assert_eq!(forward, backward);
+        assert_ne!(forward, creds(&[("alice", "a"), ("bob", "x")]));
+        assert_ne!(forward, creds(&[("alice", "a")]));
+        assert_ne!(
+            forward,
+            creds(&[("alice", "a"), ("bob", "b"), ("carol", "c")])
+        );
+        assert_ne!(forward, creds(&[("alice", "a"), ("Bob", "b")]));
+    }
+
+    #[test]
+    fn write_sorted_ignores_insertion_order() {
+        let first = creds(&[("carol", "3"), ("alice", "1"), ("bob", "2")]);
+        let second = creds(&[("bob", "2"), ("carol", "3"), ("alice", "1")]);
+
+        let mut first_out = Vec::new();
+        first.write_sorted(&mut first_out).unwrap();
+        let mut second_out = Vec::new();
+        second.write_sorted(&mut second_out).unwrap();
+        assert_eq!(first_out, second_out);
+        assert_eq!(first_out, b"alice:1\nbob:2\ncarol:3\n");
+
+        // The store itself keeps its insertion order.
+        assert_eq!(
+            first.to_string_contents().unwrap(),
+            "carol:3\nalice:1\nbob:2\n"
+        );
+    }
+
+    #[test]
+    fn display_matches_the_file_format() {
+        let mut store = creds(&[("alice", "a b"), ("bob", "b")]);
+        assert_eq!(store.to_string(), store.to_string_contents().unwrap());
+        assert_eq!(store.to_string(), "alice:a b\nbob:b\n");
+
+        store.set_delimiter('|');
+        assert_eq!(store.to_string(), "alice|a b\nbob|b\n");
+
+        assert_eq!(Creds::new().to_string(), "");
+    }
+
+    #[test]
+    fn equality_ignores_store_settings() {
+        let mut piped = creds(&[("alice", "a")]);
+        piped.set_delimiter('|');
+        assert_eq!(piped, creds(&[("alice", "a")]));
+        assert_eq!(piped.clone(), piped);
+    }
+
+    #[test]
+    fn with_capacity_behaves_like_new() {
+        let mut sized = Creds::with_capacity(100);
+        let mut plain = Creds::new();
+        assert!(sized.capacity() >= 100);
+        assert!(sized.is_empty());
+        for index in 0..200 {
+            sized.insert(format!("user{}", index), index.to_string());
+            plain.insert(format!("user{}", index), index.to_string());
+        }
+        assert_eq!(sized, plain);
+        assert_eq!(
+            sized.to_string_contents().unwrap(),
+            plain.to_string_contents().unwrap()
+        );
+
+        let contents = plain.to_string_contents().unwrap();
+        let read = Creds::reader()
+            .capacity(500)
+            .read(contents.as_bytes())
+            .unwrap();
+        assert!(read.capacity() >= 500);
+        assert_eq!(read, plain);
+    }
+
+    #[test]
+    fn shrink_to_fit_keeps_the_entries() {
+        let mut store = Creds::with_capacity(1000);
+        store.insert("alice", "a");
+        store.insert("bob", "b");
+        store.shrink_to_fit();
+        assert!(store.capacity() < 1000);
+        assert!(store.capacity() >= 2);
+        assert_eq!(store, creds(&[("alice", "a"), ("bob", "b")]));
+    }
+
+    #[test]
+    fn write_sorted_orders_usernames_lexicographically() {
+        let usernames = ["mallory", "Zed", "alice", "bob", "carol", "ab"];
+        let store: Creds = usernames.iter().map(|&name| (name, "pw")).collect();
+
+        let mut out = Vec::new();
+        store.write_sorted(&mut out).unwrap();
+        let written: Vec<&str> = std::str::from_utf8(&out)
+            .unwrap()
+            .lines()
+            .map(|line| line.split(':').next().unwrap())
+            .collect();
+        let mut expected = usernames.to_vec();
+        expected.sort_unstable();
+        assert_eq!(written, expected);
+        assert_eq!(store.usernames().collect::<Vec<_>>(), usernames);
+    }
+
+    #[test]
+    fn write_sorted_by_uses_the_comparator() {
+        let store = creds(&[("bob", "b"), ("alice", "a"), ("Carol", "c")]);
+
+        let mut out = Vec::new();
+        store.write_sorted_by(&mut out, |a, b| b.cmp(a)).unwrap();
+        assert_eq!(out, b"bob:b\nalice:a\nCarol:c\n");
+
+        let mut out = Vec::new();
+        store
+            .write_sorted_by(&mut out, |a, b| a.to_lowercase().cmp(&b.to_lowercase()))
+            .unwrap();
+        assert_eq!(out, b"alice:a\nbob:b\nCarol:c\n");
+    }
+
+    #[test]
+    fn from_env_strips_the_prefix() {
+        let vars = [
+            ("CRED_alice", "secret"),
+            ("CRED_Bob", "hunter2"),
+            ("CRED_", "no username"),
+            ("cred_carol", "wrong case"),
+            ("PATH", "/bin"),
+        ]
+        .map(|(name, value)| (name.into(), value.into()));
+        let store = Creds::from_vars(vars, "CRED_");
+        assert_eq!(store, creds(&[("alice", "secret"), ("Bob", "hunter2")]));
+        assert!(store.verify("alice", "secret"));
+        assert!(!store.contains("bob"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn from_env_skips_variables_that_are_not_unicode() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let vars = [
+            (
+                OsString::from("CRED_alice"),
+                OsString::from_vec(b"\xff".to_vec()),
+            ),
+            (
+                OsString::from_vec(b"CRED_\xff".to_vec()),
+                OsString::from("pw"),
+            ),
+            (OsString::from("CRED_bob"), OsString::from("b")),
+        ];
+        assert_eq!(Creds::from_vars(vars, "CRED_"), creds(&[("bob", "b")]));
+    }
+
+    #[test]
+    fn get_many_keeps_the_order_of_the_usernames() {
+        let store = creds(&[("alice", "a"), ("bob", "b")]);
+        assert_eq!(
+            store.get_many(&["bob", "carol", "alice", "bob", "Alice"]),
+            [Some("b"), None, Some("a"), Some("b"), None]
+        );
+        assert!(store.get_many(&[]).is_empty());
+
+        let mut folded = Creds::new_case_insensitive();
+        folded.insert("Alice", "a");
+        assert_eq!(folded.get_many(&["ALICE", "bob"]), [Some("a"), None]);
+    }
+
+    #[test]
+    fn clear_keeps_the_capacity() {
+        let mut store = Creds::with_capacity(1000);
+        for index in 0..100 {
+            store.insert(format!("user{}", index), "pw");
+        }
+        let capacity = store.capacity();
+        store.clear();
+        assert!(store.is_empty());
+        assert_eq!(store.capacity(), capacity);
+
+        store.insert("alice", "a");
+        assert_eq!(store.capacity(), capacity);
+        store.clear_and_shrink();
+        assert!(store.is_empty());
+        assert!(store.capacity() < capacity);
+    }
+
+    #[test]
+    fn read_no_duplicates_names_the_repeated_username() {
+        let contents = "alice:a\n# comment\nbob:b\nalice:other\n";
+        let error = Creds::read_no_duplicates(contents.as_bytes()).unwrap_err();
+        assert!(matches!(
+            &error,
+            CredsError::DuplicateUsername { line_number: 4, username } if username == "alice"
+        ));
+        assert_eq!(error.to_string(), "line 4 repeats username \"alice\"");
+
+        // The default reader still lets the last line win.
+        assert_eq!(
+            Creds::read(contents.as_bytes()).unwrap().get("alice"),
+            Some("other")
+        );
+        assert_eq!(
+            Creds::read_no_duplicates("alice:a\nbob:b\n".as_bytes()).unwrap(),
+            creds(&[("alice", "a"), ("bob", "b")])
+        );
+    }
+
+    #[test]
+    fn contains_checks_usernames_only() {
+        let store = creds(&[("alice", "a"), ("bob", "")]);
+        assert!(store.contains("alice"));
+        assert!(store.contains("bob"));
+        assert!(!store.contains("carol"));
+        assert!(!store.contains("Alice"));
+        assert!(!store.contains("a"));
+
+        let mut folded = Creds::new_case_insensitive();
+        folded.insert("Alice", "a");
+        assert!(folded.contains("ALICE"));
+        assert!(!folded.contains("bob"));
+    }
+
+    #[test]
+    fn insert_updates_the_modified_time() {
+        let mut store = Creds::new();
+        let before = SystemTime::now();
+        store.insert("alice", "a");
+        let meta = store.metadata("alice").unwrap().clone();
+        let created = meta.created().unwrap();
+        assert!(created >= before);
+        assert_eq!(meta.modified(), Some(created));
+
+        std::thread::sleep(Duration::from_millis(10));
+        store.insert("alice", "b");
+        let meta = store.metadata("alice").unwrap();
+        assert_eq!(meta.created(), Some(created));
+        assert!(meta.modified().unwrap() > created);
+        assert_eq!(store.metadata("bob"), None);
+    }
+
+    #[test]
+    fn legacy_files_have_no_metadata() {
+        let store = Creds::read("alice:a\nbob:b\n".as_bytes()).unwrap();
+        assert!(store.metadata("alice").unwrap().is_empty());
+        assert!(store.metadata("bob").unwrap().is_empty());
+    }
+
+    #[test]
+    fn metadata_round_trips_when_enabled() {
+        let mut store = Creds::new();
+        store.insert("alice", "a");
+        store
+            .metadata_mut("alice")
+            .unwrap()
+            .set_note(Some("on call".to_owned()));
+
+        let mut contents = Vec::new();
+        WriteOptions::new()
+            .metadata(true)
+            .write(&store, &mut contents)
+            .unwrap();
+        let text = std::str::from_utf8(&contents).unwrap();
+        assert!(text.starts_with("#meta created="), "{}", text);
+        assert!(text.ends_with(" note=on%20call\nalice:a\n"), "{}", text);
+
+        let read = ReadOptions::new()
+            .metadata(true)
+            .read(&contents[..])
+            .unwrap();
+        let meta = read.metadata("alice").unwrap();
+        assert_eq!(meta.note(), Some("on call"));
+        assert!(meta.created().is_some());
+        // Without metadata support, the line is skipped as a comment.
+        assert!(Creds::read(&contents[..])
+            .unwrap()
+            .metadata("alice")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn expiring_credentials_verify_until_the_deadline() {
+        let mut store = Creds::new();
+        let now = SystemTime::now();
+        store.insert_with_expiry("alice", "a", now + Duration::from_secs(3600));
+        store.insert_with_expiry("bob", "b", now - Duration::from_secs(1));
+        assert!(store.verify("alice", "a"));
+        assert!(!store.verify("bob", "b"));
+        assert_eq!(store.get("bob"), Some("b"));
+
+        // Setting a new password keeps the expiry time.
+        store.insert("bob", "b2");
+        assert!(!store.verify("bob", "b2"));
+        store.metadata_mut("bob").unwrap().set_expires(None);
+        assert!(store.verify("bob", "b2"));
+    }
+
+    #[test]
+    fn prune_expired_removes_only_expired_entries() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut store = creds(&[("alice", "a")]);
+        store.insert_with_expiry("bob", "b", now - Duration::from_secs(1));
+        store.insert_with_expiry("carol", "c", now);
+        store.insert_with_expiry("dave", "d", now + Duration::from_secs(1));
+
+        assert_eq!(store.prune_expired(now), 2);
+        assert_eq!(store.usernames().collect::<Vec<_>>(), ["alice", "dave"]);
+        assert_eq!(store.prune_expired(now), 0);
+    }
+
+    #[test]
+    fn upgrade_plaintext_to_hashed_leaves_hashes_alone() {
+        let params = HashParams::new(64, 1, 1).unwrap();
+        let argon2 = hash::hash_password("argon", &params);
+        let bcrypt = htpasswd::hash_bcrypt("blowfish", 4).unwrap();
+        let contents = format!(
+            "alice:plain\ncarol:{}\nbob:also plain\ndave:{}\n",
+            argon2, bcrypt
+        );
+        let mut store = Creds::read(contents.as_bytes()).unwrap();
+
+        assert_eq!(store.upgrade_plaintext_to_hashed(&params).unwrap(), 2);
+        assert_eq!(store.get("carol"), Some(argon2.as_str()));
+        assert_eq!(store.get("dave"), Some(bcrypt.as_str()));
+        for username in ["alice", "bob"] {
+            let stored = store.get(username).unwrap();
+            assert!(
+                stored.starts_with("$argon2id$v=19$m=64,t=1,p=1$"),
+                "{}",
+                stored
+            );
+        }
+        assert_eq!(
+            store.usernames().collect::<Vec<_>>(),
+            ["alice", "carol", "bob", "dave"]
+        );
+
+        let read = Creds::read(store.to_string_contents().unwrap().as_bytes()).unwrap();
+        for (username, password) in [
+            ("alice", "plain"),
+            ("bob", "also plain"),
+            ("carol", "argon"),
+            ("dave", "blowfish"),
+        ] {
+            assert!(read.verify(username, password), "{}", username);
+            assert!(!read.verify(username, "wrong"), "{}", username);
+        }
+        assert_eq!(store.upgrade_plaintext_to_hashed(&params).unwrap(), 0);
+    }
+
+    #[test]
+    fn reload_from_file_replaces_entries() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        fs::write(&path, "bob:b\n").unwrap();
+
+        let mut store = creds(&[("alice", "a")]);
+        store.reload_from_file(&path).unwrap();
+        assert_eq!(store, creds(&[("bob", "b")]));
+    }
+
+    #[test]
+    fn reload_from_malformed_file_keeps_entries() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        fs::write(&path, "not a credential\nneither is this\n").unwrap();
+
+        let mut store = creds(&[("alice", "a")]);
+        let error = store.reload_from_file(&path).unwrap_err();
+        assert!(matches!(
+            error,
+            CredsError::MalformedLine { line_number: 1, .. }
+        ));
+        assert_eq!(store, creds(&[("alice", "a")]));
+    }
+
+    #[test]
+    fn reload_from_missing_file_keeps_entries() {
+        let dir = TestDir::new();
+        let mut store = creds(&[("alice", "a")]);
+        assert!(store.reload_from_file(dir.join("missing")).is_err());
+        assert_eq!(store.get("alice"), Some("a"));
+    }
+
+    const SHADOW: &str = "\
+root:$6$salt$hash:19000:0:99999:7:::
+svc:*:19000:0:99999:7:::
+locked:!$6$salt$hash:19000::::::
+des:abJnggxhB/yWI:19000::::::
+";
+
+    #[test]
+    fn read_shadow_takes_the_first_two_fields() {
+        let hash = htpasswd::hash_bcrypt("secret", 4).unwrap();
+        let contents = format!("alice:{}:19000:0:99999:7:::\n{}", hash, SHADOW);
+        let creds = Creds::read_shadow(contents.as_bytes(), false).unwrap();
+        assert_eq!(creds.get("alice"), Some(hash.as_str()));
+        assert_eq!(creds.get("root"), Some("$6$salt$hash"));
+        assert!(creds.verify("alice", "secret"));
+        assert!(!creds.verify("alice", "wrong"));
+    }
+
+    #[test]
+    fn read_shadow_never_accepts_unverifiable_hashes() {
+        let creds = Creds::read_shadow(SHADOW.as_bytes(), false).unwrap();
+        assert_eq!(creds.len(), 4);
+        assert!(!creds.verify("root", "$6$salt$hash"));
+        assert!(!creds.verify("svc", "*"));
+        assert!(!creds.verify("locked", "!$6$salt$hash"));
+        assert!(!creds.verify("des", "abJnggxhB/yWI"));
+    }
+
+    #[test]
+    fn read_shadow_skips_locked_accounts() {
+        let creds = Creds::read_shadow(SHADOW.as_bytes(), true).unwrap();
+        assert_eq!(creds.usernames().collect::<Vec<_>>(), ["root", "des"]);
+    }
+
+    /// A store that hashes with cheap parameters, so that the tests run
+    /// quickly.
+    fn cheap_creds() -> Creds {
+        let mut creds = Creds::new();
+        creds.set_hash_params(HashParams::new(64, 1, 1).unwrap());
+        creds
+    }
+
+    #[test]
+    fn expiry_only_survives_a_file_round_trip_with_metadata() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        let mut store = creds(&[("alice", "secret")]);
+        store.insert_with_expiry(
+            "bob",
+            "hunter2",
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+        );
+        store.write_to_file(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "alice:secret\nbob:hunter2\n"
+        );
+        assert!(Creds::read_from_file(&path)
+            .unwrap()
+            .verify("bob", "hunter2"));
+
+        WriteOptions::new()
+            .metadata(true)
+            .write_to_file(&store, &path)
+            .unwrap();
+        let read = Creds::read_from_file(&path).unwrap();
+        assert!(read.verify("bob", "hunter2"));
+        assert!(read.metadata("bob").unwrap().is_empty());
+
+        let read = ReadOptions::new()
+            .metadata(true)
+            .read_from_file(&path)
+            .unwrap();
+        assert_eq!(read.get("bob"), Some("hunter2"));
+        assert!(!read.verify("bob", "hunter2"));
+        assert!(read.verify("alice", "secret"));
+        assert_eq!(read.metadata("alice").unwrap().expires(), None);
+    }
+
+    #[test]
+    fn verify_rejects_missing_users_in_hashed_stores() {
+        let mut store = cheap_creds();
+        store.insert_hashed("alice", "secret");
+        assert!(store.verify("alice", "secret"));
+        assert!(!store.verify("bob", "secret"));
+        assert!(!store.verify("bob", ""));
+    }
+
+    #[test]
+    fn change_password_checks_the_old_password() {
+        let mut store = creds(&[("alice", "old")]);
+        assert_eq!(
+            store.change_password("alice", "wrong", "new"),
+            Err(ChangeError::WrongPassword {
+                username: "alice".to_owned()
+            })
+        );
+        assert_eq!(
+            store.change_password("bob", "old", "new"),
+            Err(ChangeError::UserNotFound {
+                username: "bob".to_owned()
+            })
+        );
+        store.change_password("alice", "old", "new").unwrap();
+        assert_eq!(store.get("alice"), Some("new"));
+    }
+
+    #[test]
+    fn change_password_keeps_the_hash_scheme() {
+        let mut store = cheap_creds();
+        store.insert_hashed("argon2", "old");
+        store.insert("bcrypt", htpasswd::hash_bcrypt("old", 5).unwrap());
+        store.insert("apr1", htpasswd::hash_apr1("old"));
+        for username in ["argon2", "bcrypt", "apr1"] {
+            store.change_password(username, "old", "new").unwrap();
+            assert!(store.verify(username, "new"), "{}", username);
+            assert!(!store.verify(username, "old"), "{}", username);
+        }
+        assert!(hash::is_hashed(store.get("argon2").unwrap()));
+        assert!(store.get("bcrypt").unwrap().starts_with("$2y$05$"));
+        assert!(store.get("apr1").unwrap().starts_with("$apr1$"));
+    }
+
+    #[test]
+    fn change_password_upgrades_sha1_to_bcrypt() {
+        // The `{SHA}` hash of "password".
+        let mut store = creds(&[("alice", "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=")]);
+        store.change_password("alice", "password", "new").unwrap();
+        assert!(store.get("alice").unwrap().starts_with("$2y$"));
+        assert!(store.verify("alice", "new"));
+    }
+
+    #[test]
+    fn write_htpasswd_bcrypt_rejects_invalid_costs() {
+        let store = creds(&[("alice", "secret")]);
+        for cost in [0, 3, 32] {
+            let mut out = Vec::new();
+            assert!(matches!(
+                store.write_htpasswd_bcrypt(&mut out, cost),
+                Err(CredsError::InvalidBcryptCost { cost: rejected }) if rejected == cost
+            ));
+            assert!(out.is_empty());
+        }
+
+        let mut out = Vec::new();
+        store.write_htpasswd_bcrypt(&mut out, 4).unwrap();
+        let written = Creds::read_htpasswd(&out[..]).unwrap();
+        assert!(written.get("alice").unwrap().starts_with("$2y$04$"));
+        assert!(written.verify("alice", "secret"));
+    }
+
+    #[test]
+    fn try_insert_rejects_what_write_cannot_represent() {
+        let mut store = Creds::new();
+        assert_eq!(store.try_insert("alice", "a:b"), Ok(None));
+        assert_eq!(
+            store.try_insert("", "a"),
+            Err(InvalidCredential::EmptyUsername)
+        );
+        for username in ["a:b", "a\nb", "a\rb", "#alice", "  #alice"] {
+            assert!(matches!(
+                store.try_insert(username, "a"),
+                Err(InvalidCredential::IllegalUsername { .. })
+            ));
+        }
+        for password in ["a\nb", "a\r", "\r\n"] {
+            assert!(matches!(
+                store.try_insert("bob", password),
+                Err(InvalidCredential::IllegalPassword { .. })
+            ));
+        }
+        assert_eq!(written(&store), "alice:a:b\n");
+    }
+
+    #[test]
+    fn try_insert_errors_name_the_store_delimiter() {
+        let mut store = Creds::new();
+        store.set_delimiter('|');
+        assert_eq!(store.try_insert("a:b", "c"), Ok(None));
+        let error = store.try_insert("a|b", "c").unwrap_err();
+        assert_eq!(
+            error,
+            InvalidCredential::IllegalUsername {
+                username: "a|b".to_owned(),
+                delimiter: '|',
+                comment: '#',
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "username \"a|b\" contains '|' or a line break, or starts with '#'"
+        );
+    }
+
+    #[test]
+    fn expired_credentials_cannot_change_their_password() {
+        let mut store = Creds::new();
+        let past = SystemTime::now() - Duration::from_secs(1);
+        store.insert_with_expiry("alice", "old", past);
+        assert_eq!(
+            store.change_password("alice", "wrong", "new"),
+            Err(ChangeError::WrongPassword {
+                username: "alice".to_owned()
+            })
+        );
+        assert_eq!(
+            store.change_password("alice", "old", "new"),
+            Err(ChangeError::Expired {
+                username: "alice".to_owned()
+            })
+        );
+        assert_eq!(store.get("alice"), Some("old"));
+    }
+
+    #[test]
+    fn modifying_through_entries_records_the_change() {
+        let mut store = Creds::new();
+        store.insert("alice", "a");
+        *store.metadata_mut("alice").unwrap() = CredMeta::default();
+        match store.entry("alice") {
+            Entry::Occupied(mut entry) => entry.get_mut().push('!'),
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert_eq!(store.get("alice"), Some("a!"));
+        assert!(store.metadata("alice").unwrap().modified().is_some());
+
+        *store.metadata_mut("alice").unwrap() = CredMeta::default();
+        store
+            .entry("alice")
+            .and_modify(|password| password.push('?'));
+        assert_eq!(store.get("alice"), Some("a!?"));
+        assert!(store.metadata("alice").unwrap().modified().is_some());
+
+        *store.metadata_mut("alice").unwrap() = CredMeta::default();
+        store.entry("alice").or_insert("b");
+        assert_eq!(store.metadata("alice").unwrap().modified(), None);
+    }
+
+    #[test]
+    fn verify_all_checks_every_pair_in_order() {
+        let mut store = cheap_creds();
+        store.insert("alice", "a");
+        store.insert_hashed("bob", "b");
+        let past = SystemTime::now() - Duration::from_secs(1);
+        store.insert_with_expiry("carol", "c", past);
+        assert_eq!(
+            store.verify_all([
+                ("bob", "b"),
+                ("alice", "wrong"),
+                ("alice", "a"),
+                ("bob", "a"),
+                ("carol", "c"),
+                ("dave", "d"),
+            ]),
+            [true, false, true, false, false, false]
+        );
+        assert!(store.verify_all([]).is_empty());
+    }
+
+    #[test]
+    fn missing_usernames_are_checked_against_a_dummy_hash() {
+        let mut store = cheap_creds();
+        store.insert_hashed("alice", "a");
+        assert!(!store.verify("bob", "a"));
+        let dummy_hash = store.dummy_hash.get().unwrap();
+        assert!(hash::verify_hash("", dummy_hash));
+        assert!(dummy_hash.contains("m=64,t=1,p=1"));
+
+        store.set_hash_params(HashParams::new(128, 1, 1).unwrap());
+        assert!(store.dummy_hash.get().is_none());
+        assert!(!store.verify("bob", "a"));
+        assert!(store.dummy_hash.get().unwrap().contains("m=128,t=1,p=1"));
+    }
+
+    #[test]
+    fn read_auto_reads_the_line_format() {
+        let read = Creds::read_auto(&b"alice:a\nbob:$apr1$x$y\n"[..]).unwrap();
+        assert_eq!(read.get("alice"), Some("a"));
+        assert_eq!(read.get("bob"), Some("$apr1$x$y"));
+
+        let read = Creds::read_auto(&b"{weird}:pw\n"[..]).unwrap();
+        assert_eq!(read.get("{weird}"), Some("pw"));
+
+        assert!(matches!(
+            Creds::read_auto(&b"alice\n"[..]),
+            Err(CredsError::UnrecognizedFormat)
+        ));
+    }
+
+    #[test]
+    fn read_auto_reads_json_with_the_json_feature() {
+        let read = Creds::read_auto(&b"{\"alice\": \"a\"}"[..]);
+        if cfg!(feature = "json") {
+            assert_eq!(read.unwrap().get("alice"), Some("a"));
+        } else {
+            assert!(matches!(read, Err(CredsError::UnrecognizedFormat)));
+        }
+    }
+
+    #[test]
+    fn upgrade_plaintext_to_hashed_refuses_weak_hashes() {
+        let params = HashParams::new(64, 1, 1).unwrap();
+        for weak in [
+            htpasswd::hash_apr1("md5"),
+            "{SHA}qUqP5cyxm6YcTAhz05Hph5gvu9M=".to_owned(),
+        ] {
+            let mut store = creds(&[("alice", "plain"), ("bob", &weak)]);
+            match store.upgrade_plaintext_to_hashed(&params) {
+                Err(CredsError::WeakHash { username }) => assert_eq!(username, "bob"),
+                other => panic!("{:?}", other),
+            }
+            assert_eq!(store.get("alice"), Some("plain"));
+            assert_eq!(store.get("bob"), Some(weak.as_str()));
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! CSV import and export, enabled by the `csv` feature.
+//!
+//! The CSV format has a header row naming the `username` and `password`
+//! columns, followed by one row per credential. Fields are quoted as in
+//! RFC 4180, so they may contain commas, quotes, and newlines.
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    creds::{Creds, CredsError},
+    meta::CredMeta,
+};
+
+impl Creds {
+    /// Parses a store from CSV data in the given reader.
+    ///
+    /// The first row must be a header with `username` and `password` columns,
+    /// matched case-insensitively; they may be in either order, and other
+    /// columns are ignored. Invalid CSV, a missing column, or a row with the
+    /// wrong number of fields is reported as an
+    /// [`InvalidData`](io::ErrorKind::InvalidData) [`CredsError::Io`] error.
+    /// As with [`Creds::read`], a later row overwrites an earlier one with the
+    /// same username.
+    pub fn from_csv(reader: impl Read) -> Result<Self, CredsError> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader.headers().map_err(csv_error)?;
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim().eq_ignore_ascii_case(name))
+                .ok_or_else(|| {
+                    CredsError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("CSV header has no {:?} column", name),
+                    ))
+                })
+        };
+        let username_column = column("username")?;
+        let password_column = column("password")?;
+
+        let mut creds = Self::new();
+        for record in reader.into_records() {
+            let record = record.map_err(csv_error)?;
+            creds.insert_read(
+                record[username_column].to_owned(),
+                record[password_column].to_owned(),
+                CredMeta::default(),
+            );
+        }
+        Ok(creds)
+    }
+
+    /// Writes the store into the given writer as CSV, with a header row.
+    ///
+    /// Unlike the line format, CSV can represent any username and password,
+    /// so this only fails if writing does.
+    pub fn to_csv(&self, writer: impl Write) -> Result<(), CredsError> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer
+            .write_record(["username", "password"])
+            .map_err(csv_error)?;
+        for (username, password) in self {
+            writer
+                .write_record([username, password])
+                .map_err(csv_error)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn csv_error(error: csv::Error) -> CredsError {
+    if error.is_io_error() {
+        match error.into_kind() {
+            csv::ErrorKind::Io(error) => CredsError::Io(error),
+            _ => unreachable!(),
+        }
+    } else {
+        CredsError::Io(io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_csv_string(creds: &Creds) -> String {
+        let mut out = Vec::new();
+        creds.to_csv(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn quoted_fields_round_trip() {
+        let creds: Creds = [
+            ("alice", "comma, inside"),
+            ("bob", "embedded\nnewline"),
+            ("carol", "say \"hi\""),
+        ]
+        .into_iter()
+        .collect();
+        let csv = to_csv_string(&creds);
+        assert_eq!(
+            csv,
+            "username,password\n\
+             alice,\"comma, inside\"\n\
+             bob,\"embedded\nnewline\"\n\
+             carol,\"say \"\"hi\"\"\"\n"
+        );
+
+        let read = Creds::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(read, creds);
+        assert_eq!(read.get("alice"), Some("comma, inside"));
+        assert_eq!(read.get("bob"), Some("embedded\nnewline"));
+    }
+
+    #[test]
+    fn header_columns_may_be_reordered() {
+        let csv = "Password,email,USERNAME\nsecret,a@example.com,alice\n";
+        let read = Creds::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read.get("alice"), Some("secret"));
+    }
+
+    #[test]
+    fn missing_columns_are_rejected() {
+        let error = Creds::from_csv("username,pass\nalice,secret\n".as_bytes()).unwrap_err();
+        assert!(
+            matches!(&error, CredsError::Io(error) if error.kind() == io::ErrorKind::InvalidData)
+        );
+        assert!(Creds::from_csv("username,password\nalice\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn empty_store_writes_only_the_header() {
+        assert_eq!(to_csv_string(&Creds::new()), "username,password\n");
+        assert!(Creds::from_csv("username,password\n".as_bytes())
+            .unwrap()
+            .is_empty());
+    }
+}
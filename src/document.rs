@@ -0,0 +1,383 @@
+//! Editing credential files while keeping their comments and layout.
+//!
+//! [`Creds::read`] only keeps the credentials of a file, so writing them back
+//! loses comments, blank lines, and any other formatting. A [`Document`]
+//! remembers every line of the file as well, and writes unchanged entries back
+//! exactly as they were read:
+//!
+//! - Entries whose password or [metadata](crate::meta) changed are rewritten
+//!   in place.
+//! - Entries that were removed are dropped, along with any other lines for
+//!   the same username.
+//! - New entries are appended at the end of the file.
+//! - Metadata lines are rewritten and dropped along with the entry they
+//!   describe. Changed and new entries get metadata lines as with
+//!   [`WriteOptions::metadata`], set like the [`ReadOptions::metadata`] the
+//!   document was read with.
+//! - Comments, blank lines, malformed lines, and line endings are kept as
+//!   they are.
+//!
+//! Reading a file into a document and writing it without changes produces the
+//! same bytes.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use zeroize::Zeroizing;
+
+use crate::{
+    creds::{Creds, CredsError},
+    file,
+    format::{zeroize_field, LineEnding, ReadOptions, WriteOptions},
+    meta::CredMeta,
+};
+
+/// A credential file, with its comments and layout.
+///
+/// Read one with [`Document::read`] or [`ReadOptions::read_document`], change
+/// the credentials through [`Document::creds_mut`], and write it back with
+/// [`Document::write`]. Changed and new entries are written in the format the
+/// document was read with.
+#[derive(Clone)]
+pub struct Document {
+    creds: Creds,
+    lines: Vec<(Zeroizing<String>, LineEnding)>,
+    options: ReadOptions,
+}
+
+impl Document {
+    /// Parses a credentials file from the given reader with the default
+    /// options.
+    ///
+    /// See also: [`Creds::read`]
+    pub fn read(reader: impl BufRead) -> Result<Self, CredsError> {
+        ReadOptions::new().read_document(reader)
+    }
+
+    /// Parses a credentials file from the given file with the default options.
+    pub fn read_from_file(path: impl AsRef<Path>) -> Result<Self, CredsError> {
+        ReadOptions::new().read_document_from_file(path)
+    }
+
+    /// Returns the credentials in the document.
+    pub fn creds(&self) -> &Creds {
+        &self.creds
+    }
+
+    /// Returns the credentials in the document, for changing them.
+    pub fn creds_mut(&mut self) -> &mut Creds {
+        &mut self.creds
+    }
+
+    /// Converts the document into its credentials, discarding the layout.
+    pub fn into_creds(self) -> Creds {
+        self.creds
+    }
+
+    /// Writes the document into the given writer.
+    ///
+    /// See the [module documentation](crate::document) for how changes to
+    /// the credentials are written. Returns [`CredsError::IllegalCharacter`]
+    /// if a changed or new entry cannot be written, like [`Creds::write`].
+    pub fn write(&self, mut writer: impl Write) -> Result<(), CredsError> {
+        let write_options = self.options.write_options();
+        let newline = match self.lines.first() {
+            Some((_, LineEnding::CrLf)) => LineEnding::CrLf,
+            _ => LineEnding::Lf,
+        };
+
+        // Only one line for each username takes effect when reading, usually
+        // the last one, so that is the one that gets updated.
+        let keep_first = self.options.keeps_first_duplicate();
+        let mut live_lines = HashMap::new();
+        for (index, (line, _)) in self.lines.iter().enumerate() {
+            if let Some((username, password)) = self.options.parse_entry(0, line)? {
+                if let Some(stored) = self.creds.stored_username(&username) {
+                    if keep_first {
+                        live_lines.entry(stored).or_insert(index);
+                    } else {
+                        live_lines.insert(stored, index);
+                    }
+                }
+                zeroize_field(password);
+            }
+        }
+
+        let mut out = LineWriter {
+            writer: &mut writer,
+            pending: None,
+            newline,
+        };
+        // A metadata line describes the entry on the next line, so it is held
+        // back until that entry is written, and rewritten or dropped with it.
+        let mut meta_line = None;
+        for (index, (line, ending)) in self.lines.iter().enumerate() {
+            if let Some(meta) = self.options.parse_meta(line) {
+                if let Some((line, ending, _)) = meta_line.replace((line, *ending, meta)) {
+                    out.raw(line, ending)?;
+                }
+                continue;
+            }
+            let read_meta = meta_line.take();
+            let (username, password) = match self.options.parse_entry(0, line)? {
+                Some(entry) => entry,
+                None => {
+                    out.raw_meta(read_meta)?;
+                    out.raw(line, *ending)?;
+                    continue;
+                }
+            };
+            let stored = self.creds.stored_username(&username);
+            let unchanged = match stored {
+                Some(stored) if live_lines[stored] == index => {
+                    let meta = self.creds.metadata(stored).cloned().unwrap_or_default();
+                    let read = read_meta.as_ref().map(|(_, _, meta)| meta.clone());
+                    stored == username
+                        && self.creds.get(stored) == Some(&*password)
+                        && write_options.written_meta(&meta) == read.unwrap_or_default()
+                }
+                Some(_) => true,
+                None => false,
+            };
+            zeroize_field(password);
+            match stored {
+                Some(_) if unchanged => {
+                    out.raw_meta(read_meta)?;
+                    out.raw(line, *ending)?;
+                }
+                Some(stored) => out.entry(&self.creds, &write_options, stored, *ending)?,
+                // Removed from the store.
+                None => {}
+            }
+        }
+        out.raw_meta(meta_line)?;
+        for username in self.creds.usernames() {
+            if !live_lines.contains_key(username) {
+                out.entry(&self.creds, &write_options, username, newline)?;
+            }
+        }
+        out.finish()
+    }
+
+    /// Writes the document to the given file.
+    ///
+    /// The file is replaced atomically, like with [`Creds::write_to_file`].
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> Result<(), CredsError> {
+        file::write_atomic(path.as_ref(), |writer| self.write(writer))
+    }
+}
+
+impl fmt::Debug for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Document")
+            .field("creds", &self.creds)
+            .field("lines", &self.lines.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ReadOptions {
+    /// Parses a credentials file from the given reader, keeping its comments
+    /// and layout.
+    ///
+    /// This reads the same credentials as [`ReadOptions::read`].
+    pub fn read_document(&self, mut reader: impl BufRead) -> Result<Document, CredsError> {
+        let mut creds = self.new_creds();
+        let mut lines = Vec::new();
+        let mut line_reader = self.line_reader();
+        let mut pending_meta = None;
+        while let Some((line_number, line, ending)) = line_reader.next_line(&mut reader)? {
+            self.read_line(&mut creds, &mut pending_meta, line_number, &line)?;
+            lines.push((line, ending));
+        }
+        Ok(Document {
+            creds,
+            lines,
+            // Malformed lines have already been rejected if needed, and are
+            // kept as they are from now on.
+            options: self.clone().strict(false),
+        })
+    }
+
+    /// Parses a credentials file from the given file, keeping its comments
+    /// and layout.
+    ///
+    /// See also: [`ReadOptions::read_document`]
+    pub fn read_document_from_file(&self, path: impl AsRef<Path>) -> Result<Document, CredsError> {
+        let file = File::open(path)?;
+        self.read_document(BufReader::new(file))
+    }
+}
+
+/// Writes lines, delaying each line ending until the next line so that a
+/// final line without one can be continued.
+struct LineWriter<W> {
+    writer: W,
+    pending: Option<LineEnding>,
+    /// The line ending added to a final line that is continued.
+    newline: LineEnding,
+}
+
+impl<W: Write> LineWriter<W> {
+    fn start_line(&mut self) -> Result<(), CredsError> {
+        match self.pending.take() {
+            Some(LineEnding::None) => self.writer.write_all(self.newline.as_str().as_bytes())?,
+            Some(LineEnding::Cr) => self.writer.write_all(b"\r\n")?,
+            Some(ending) => self.writer.write_all(ending.as_str().as_bytes())?,
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn raw(&mut self, line: &str, ending: LineEnding) -> Result<(), CredsError> {
+        self.start_line()?;
+        self.writer.write_all(line.as_bytes())?;
+        self.pending = Some(ending);
+        Ok(())
+    }
+
+    /// Writes a metadata line as it was read, if there is one.
+    fn raw_meta(
+        &mut self,
+        meta_line: Option<(&Zeroizing<String>, LineEnding, CredMeta)>,
+    ) -> Result<(), CredsError> {
+        match meta_line {
+            Some((line, ending, _)) => self.raw(line, ending),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the entry for the given username in the store, preceded by its
+    /// metadata line if the options write one.
+    fn entry(
+        &mut self,
+        creds: &Creds,
+        options: &WriteOptions,
+        username: &str,
+        ending: LineEnding,
+    ) -> Result<(), CredsError> {
+        let meta = creds.metadata(username).cloned().unwrap_or_default();
+        if let Some(line) = options.meta_line(&meta) {
+            self.raw(&line, self.newline)?;
+        }
+        self.start_line()?;
+        let password = creds.get(username).unwrap_or_default();
+        options.write_entry(&mut self.writer, username, password)?;
+        self.pending = Some(ending);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), CredsError> {
+        if let Some(ending) = self.pending.take() {
+            self.writer.write_all(ending.as_str().as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn rewrite(contents: &str, change: impl FnOnce(&mut Creds)) -> String {
+        let mut document = Document::read(contents.as_bytes()).unwrap();
+        change(document.creds_mut());
+        let mut out = Vec::new();
+        document.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn unchanged_document_round_trips() {
+        let contents = "# users\r\nalice:secret\r\n\r\nbob:hunter2";
+        assert_eq!(rewrite(contents, |_| {}), contents);
+    }
+
+    #[test]
+    fn appends_after_a_final_line_without_an_ending() {
+        assert_eq!(
+            rewrite("alice:secret", |creds| creds.insert("bob", "hunter2")),
+            "alice:secret\nbob:hunter2\n"
+        );
+        assert_eq!(
+            rewrite("alice:secret\r\nbob:old", |creds| creds
+                .insert("carol", "pw")),
+            "alice:secret\r\nbob:old\r\ncarol:pw\r\n"
+        );
+    }
+
+    #[test]
+    fn rewrites_changed_entries_in_place() {
+        assert_eq!(
+            rewrite("# users\nalice:old\nbob:pw\n", |creds| {
+                creds.insert("alice", "new");
+            }),
+            "# users\nalice:new\nbob:pw\n"
+        );
+    }
+
+    fn rewrite_with_metadata(contents: &str, change: impl FnOnce(&mut Creds)) -> String {
+        let mut document = ReadOptions::new()
+            .metadata(true)
+            .read_document(contents.as_bytes())
+            .unwrap();
+        change(document.creds_mut());
+        let mut out = Vec::new();
+        document.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn removed_entries_take_their_metadata_line_along() {
+        let contents = "#meta created=1 modified=2 note=first\nalice:a\nbob:b\n";
+        let written = rewrite_with_metadata(contents, |creds| {
+            creds.remove("alice");
+        });
+        assert_eq!(written, "bob:b\n");
+        let read = ReadOptions::new()
+            .metadata(true)
+            .read(written.as_bytes())
+            .unwrap();
+        assert!(read.metadata("bob").unwrap().is_empty());
+    }
+
+    #[test]
+    fn unchanged_metadata_lines_are_kept_as_they_are() {
+        let contents = "#meta  modified=2 unknown=x\nalice:a\n# users\n#meta note=x\n\nbob:b\n";
+        assert_eq!(rewrite_with_metadata(contents, |_| {}), contents);
+    }
+
+    #[test]
+    fn changed_and_new_entries_get_metadata_lines() {
+        let written = rewrite_with_metadata("#meta created=1 modified=2\nalice:a\n", |creds| {
+            creds.insert("alice", "new");
+            let expires = SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+            creds.insert_with_expiry("bob", "b", expires);
+        });
+        let lines: Vec<_> = written.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", written);
+        assert!(
+            lines[0].starts_with("#meta created=1 modified="),
+            "{}",
+            written
+        );
+        assert_ne!(lines[0], "#meta created=1 modified=2");
+        assert_eq!(lines[1], "alice:new");
+        assert!(lines[2].ends_with(" expires=1800000000"), "{}", written);
+        assert_eq!(lines[3], "bob:b");
+
+        let read = ReadOptions::new()
+            .metadata(true)
+            .read(written.as_bytes())
+            .unwrap();
+        assert_eq!(read.get("alice"), Some("new"));
+        assert!(read.metadata("bob").unwrap().expires().is_some());
+    }
+}
@@ -0,0 +1,284 @@
+//! Encryption of whole credential files with a passphrase.
+//!
+//! An encrypted file consists of a header followed by the ChaCha20-Poly1305
+//! encryption of the regular line format:
+//!
+//! | Bytes | Contents                                          |
+//! |-------|---------------------------------------------------|
+//! | 8     | The magic bytes `ope-enc` followed by version `1` |
+//! | 12    | Argon2id memory cost, iterations, and parallelism |
+//! | 16    | Argon2id salt                                     |
+//! | 12    | ChaCha20-Poly1305 nonce                           |
+//! | rest  | Ciphertext and authentication tag                 |
+//!
+//! The costs are little-endian `u32`s. The encryption key is derived from the
+//! passphrase and salt with Argon2id, so the passphrase is all that is needed
+//! to read the file back. The header is authenticated along with the
+//! ciphertext, so any modification of the file is detected.
+//!
+//! The costs are read before the header can be authenticated, so a crafted
+//! file could otherwise make the reader derive a key with unbounded memory or
+//! time. Files are only written and read with costs of at most
+//! [`MAX_MEMORY_KIB`], [`MAX_ITERATIONS`], and [`MAX_PARALLELISM`].
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    creds::{Creds, CredsError},
+    file,
+    format::WriteOptions,
+    hash::{self, HashParams},
+};
+
+const MAGIC: &[u8; 8] = b"ope-enc\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + 12 + SALT_LEN + NONCE_LEN;
+
+/// The largest Argon2id memory cost of an encrypted file, 1 GiB.
+pub const MAX_MEMORY_KIB: u32 = hash::MAX_MEMORY_KIB;
+
+/// The largest Argon2id iteration count of an encrypted file.
+pub const MAX_ITERATIONS: u32 = hash::MAX_ITERATIONS;
+
+/// The largest Argon2id parallelism of an encrypted file.
+pub const MAX_PARALLELISM: u32 = hash::MAX_PARALLELISM;
+
+impl Creds {
+    /// Writes the credentials into the given writer, encrypted with the given
+    /// passphrase.
+    ///
+    /// The key is derived from the passphrase with Argon2id, using the store's
+    /// [hash parameters](Creds::set_hash_params) and a random salt. See the
+    /// [module documentation](crate::encrypt) for the file format.
+    ///
+    /// The credentials must be representable in the plain format; see
+    /// [`Creds::write`]. Returns an [`InvalidInput`](io::ErrorKind::InvalidInput)
+    /// [`CredsError::Io`] error if the hash parameters exceed the
+    /// [limits](crate::encrypt) of the format, since the file could not be
+    /// read back.
+    pub fn write_encrypted(
+        &self,
+        mut writer: impl Write,
+        passphrase: &str,
+    ) -> Result<(), CredsError> {
+        let params = self.hash_params();
+        if !params.within_limits() {
+            return Err(CredsError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "hash parameters exceed the limits of encrypted files",
+            )));
+        }
+        let mut plaintext = Zeroizing::new(Vec::new());
+        WriteOptions::new().write(self, &mut *plaintext)?;
+
+        let mut salt = [0; SALT_LEN];
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&params.memory_kib().to_le_bytes());
+        header.extend_from_slice(&params.iterations().to_le_bytes());
+        header.extend_from_slice(&params.parallelism().to_le_bytes());
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&nonce);
+
+        let cipher = cipher(passphrase, &salt, params);
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: &header,
+                },
+            )
+            .expect("encrypting an in-memory buffer cannot fail");
+
+        writer.write_all(&header)?;
+        writer.write_all(&ciphertext)?;
+        Ok(())
+    }
+
+    /// Parses credentials from the given reader, decrypting them with the
+    /// given passphrase.
+    ///
+    /// Returns [`CredsError::Decryption`] if the passphrase is wrong, or if
+    /// the input is not an encrypted credential file or has been modified.
+    /// Files whose costs exceed the [limits](crate::encrypt) are rejected the
+    /// same way, before deriving the key.
+    pub fn read_encrypted(mut reader: impl Read, passphrase: &str) -> Result<Self, CredsError> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        if contents.len() < HEADER_LEN || !contents.starts_with(MAGIC) {
+            return Err(CredsError::Decryption);
+        }
+        let (header, ciphertext) = contents.split_at(HEADER_LEN);
+        let (costs, rest) = header[MAGIC.len()..].split_at(12);
+        let (salt, nonce) = rest.split_at(SALT_LEN);
+        let cost =
+            |index: usize| u32::from_le_bytes(costs[index * 4..index * 4 + 4].try_into().unwrap());
+        let params =
+            HashParams::new(cost(0), cost(1), cost(2)).map_err(|_| CredsError::Decryption)?;
+        if !params.within_limits() {
+            return Err(CredsError::Decryption);
+        }
+
+        let cipher = cipher(passphrase, salt, &params);
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| CredsError::Decryption)?;
+
+        let mut creds = Self::read(&plaintext[..])?;
+        creds.set_hash_params(params);
+        Ok(creds)
+    }
+
+    /// Writes the credential store to the given file, encrypted with the given
+    /// passphrase.
+    ///
+    /// The file is replaced atomically and gets the same permissions as with
+    /// [`Creds::write_to_file`].
+    ///
+    /// See also: [`Creds::write_encrypted`]
+    pub fn write_encrypted_to_file(
+        &self,
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<(), CredsError> {
+        file::write_atomic(path.as_ref(), |writer| {
+            self.write_encrypted(writer, passphrase)
+        })
+    }
+
+    /// Parses a credential store from the given file, decrypting it with the
+    /// given passphrase.
+    ///
+    /// See also: [`Creds::read_encrypted`]
+    pub fn read_encrypted_from_file(
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<Self, CredsError> {
+        Self::read_encrypted(File::open(path)?, passphrase)
+    }
+}
+
+/// Derives the file encryption key from a passphrase.
+fn cipher(passphrase: &str, salt: &[u8], params: &HashParams) -> ChaCha20Poly1305 {
+    let mut key = Zeroizing::new([0; 32]);
+    params
+        .argon2()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+        .expect("key derivation with validated parameters cannot fail");
+    ChaCha20Poly1305::new(Key::from_slice(&*key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters, so that the tests run quickly.
+    fn test_creds() -> Creds {
+        let mut creds = Creds::new();
+        creds.set_hash_params(HashParams::new(64, 1, 1).unwrap());
+        creds.insert("alice", "hunter2");
+        creds
+    }
+
+    fn encrypted() -> Vec<u8> {
+        let mut contents = Vec::new();
+        test_creds()
+            .write_encrypted(&mut contents, "passphrase")
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn round_trips_with_the_right_passphrase() {
+        let contents = encrypted();
+        assert!(!contents.windows(7).any(|window| window == b"hunter2"));
+        let read = Creds::read_encrypted(&contents[..], "passphrase").unwrap();
+        assert_eq!(read.iter().collect::<Vec<_>>(), [("alice", "hunter2")]);
+    }
+
+    #[test]
+    fn rejects_the_wrong_passphrase() {
+        let contents = encrypted();
+        assert!(matches!(
+            Creds::read_encrypted(&contents[..], "wrong"),
+            Err(CredsError::Decryption)
+        ));
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let dir = crate::file::TestDir::new();
+        let path = dir.join("creds.enc");
+        test_creds()
+            .write_encrypted_to_file(&path, "passphrase")
+            .unwrap();
+        let read = Creds::read_encrypted_from_file(&path, "passphrase").unwrap();
+        assert_eq!(read.iter().collect::<Vec<_>>(), [("alice", "hunter2")]);
+        assert!(matches!(
+            Creds::read_encrypted_from_file(&path, "wrong"),
+            Err(CredsError::Decryption)
+        ));
+    }
+
+    #[test]
+    fn rejects_huge_costs_before_deriving_the_key() {
+        let mut contents = encrypted();
+        let costs = MAGIC.len();
+        contents[costs..costs + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Creds::read_encrypted(&contents[..], "passphrase"),
+            Err(CredsError::Decryption)
+        ));
+
+        let mut contents = encrypted();
+        contents[costs + 4..costs + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Creds::read_encrypted(&contents[..], "passphrase"),
+            Err(CredsError::Decryption)
+        ));
+    }
+
+    #[test]
+    fn refuses_to_write_costs_above_the_limits() {
+        let mut creds = test_creds();
+        creds.set_hash_params(HashParams::new(MAX_MEMORY_KIB + 1, 1, 1).unwrap());
+        assert!(matches!(
+            creds.write_encrypted(Vec::new(), "passphrase"),
+            Err(CredsError::Io(error)) if error.kind() == io::ErrorKind::InvalidInput
+        ));
+    }
+
+    #[test]
+    fn detects_modified_header() {
+        let mut contents = encrypted();
+        contents[MAGIC.len() + 12] ^= 1;
+        assert!(matches!(
+            Creds::read_encrypted(&contents[..], "passphrase"),
+            Err(CredsError::Decryption)
+        ));
+    }
+}
@@ -0,0 +1,346 @@
+//! Helpers for writing credential files safely.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::creds::CredsError;
+
+/// Writes a file atomically, by writing to a temporary file in the same
+/// directory and then renaming it over the destination.
+///
+/// Readers of the destination only ever see either its old contents or the
+/// complete new contents. The temporary file is in the same directory so that
+/// the rename does not cross filesystems, which would not be atomic. If
+/// writing fails, the temporary file is removed and the destination is left
+/// untouched.
+///
+/// If the destination already exists, its permissions are copied to the new
+/// file, so replacing it does not change who can read it. Otherwise, on Unix,
+/// the file is created with mode `0600`, readable and writable only by its
+/// owner; on other platforms, it gets the default permissions.
+///
+/// On Unix, the directory is synced after the rename as well, so that the
+/// new file is still in place after a crash.
+pub(crate) fn write_atomic<F>(path: &Path, write: F) -> Result<(), CredsError>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), CredsError>,
+{
+    let temp_path = temp_path(path)?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options.open(&temp_path)?;
+
+    let result = (|| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        let file = writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+        fs::rename(&temp_path, path)?;
+        sync_parent(path)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Appends to a file, creating it if it does not exist.
+///
+/// A new file is created with the same permissions as in [`write_atomic`]. If
+/// an existing file does not end with a newline, one is added first, so that
+/// the appended lines do not run into its last line.
+pub(crate) fn append<F>(path: &Path, write: F) -> Result<(), CredsError>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), CredsError>,
+{
+    let mut options = OpenOptions::new();
+    options.read(true).append(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+
+    if file.seek(SeekFrom::End(0))? > 0 {
+        let mut last = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last != *b"\n" {
+            file.write_all(b"\n")?;
+        }
+    }
+
+    let mut writer = BufWriter::new(file);
+    write(&mut writer)?;
+    let file = writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// The async equivalent of [`write_atomic`], writing already-serialized
+/// contents.
+#[cfg(feature = "tokio")]
+pub(crate) async fn write_atomic_async(path: &Path, contents: &[u8]) -> Result<(), CredsError> {
+    use tokio::{fs, io::AsyncWriteExt};
+
+    let temp_path = temp_path(path)?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&temp_path).await?;
+
+    let result = async {
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        if let Ok(metadata) = fs::metadata(path).await {
+            fs::set_permissions(&temp_path, metadata.permissions()).await?;
+        }
+        fs::rename(&temp_path, path).await?;
+        #[cfg(unix)]
+        fs::File::open(parent_dir(path)).await?.sync_all().await?;
+        Ok(())
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+/// Opens and locks the lock file for the given path exclusively, blocking
+/// until the lock is available.
+///
+/// The lock file is the path with `.lock` appended, and is created if it does
+/// not exist. Locking the credentials file itself would not work, because
+/// [`write_atomic`] replaces it with a new file. The lock is released when the
+/// returned file is closed.
+pub(crate) fn lock_exclusive(path: &Path) -> io::Result<File> {
+    let file = create_lock_file(&lock_path(path)?)?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Opens and locks the lock file for the given path with a shared lock,
+/// blocking while another process holds an exclusive lock.
+///
+/// Unlike [`lock_exclusive`], this only needs read access to an existing lock
+/// file, so that read-only users of a credentials file can take it. If there
+/// is no lock file and it cannot be created either, nobody can have locked the
+/// file, and `None` is returned instead of an error.
+pub(crate) fn lock_shared(path: &Path) -> io::Result<Option<File>> {
+    let lock_path = lock_path(path)?;
+    let file = match File::open(&lock_path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            match create_lock_file(&lock_path) {
+                Ok(file) => file,
+                Err(_) => return Ok(None),
+            }
+        }
+        Err(error) => return Err(error),
+    };
+    file.lock_shared()?;
+    Ok(Some(file))
+}
+
+/// Returns the path of the lock file for the given path.
+fn lock_path(path: &Path) -> io::Result<PathBuf> {
+    let mut lock_name = path
+        .file_name()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "credential file path has no file name",
+            )
+        })?
+        .to_owned();
+    lock_name.push(".lock");
+    Ok(path.with_file_name(lock_name))
+}
+
+/// Opens a lock file for writing, creating it if it does not exist.
+fn create_lock_file(lock_path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(lock_path)
+}
+
+/// Syncs the directory containing the given path, so that a rename into it
+/// is durable.
+///
+/// This does nothing on platforms other than Unix, where directories cannot
+/// be opened like files.
+fn sync_parent(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(parent_dir(path))?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Returns the directory containing the given path, which is the current
+/// directory for a bare file name.
+#[cfg(unix)]
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Picks a temporary file name next to the given path.
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "credential file path has no file name",
+        )
+    })?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(
+        ".{}.{}.tmp",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    Ok(path.with_file_name(temp_name))
+}
+
+/// A temporary directory for tests, removed with its contents when dropped.
+#[cfg(test)]
+pub(crate) struct TestDir {
+    path: PathBuf,
+}
+
+#[cfg(test)]
+impl TestDir {
+    pub(crate) fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "ope-test-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    /// Returns the path of the given file in the directory.
+    pub(crate) fn join(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// Returns the names of the files in the directory, sorted.
+    pub(crate) fn file_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(&self.path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::creds::Creds;
+
+    #[test]
+    fn write_atomic_replaces_the_file_without_leaving_temp_files() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        fs::write(&path, "old:contents\n").unwrap();
+        let creds: Creds = [("alice", "secret")].into_iter().collect();
+        creds.write_to_file(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "alice:secret\n");
+        assert_eq!(dir.file_names(), ["creds"]);
+    }
+
+    #[test]
+    fn write_atomic_cleans_up_after_errors() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        fs::write(&path, "old:contents\n").unwrap();
+        let creds: Creds = [("ali:ce", "secret")].into_iter().collect();
+        assert!(matches!(
+            creds.write_to_file(&path),
+            Err(CredsError::IllegalCharacter { .. })
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old:contents\n");
+        assert_eq!(dir.file_names(), ["creds"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_keeps_existing_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        Creds::new().write_to_file(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_files_are_only_readable_by_their_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        let creds: Creds = [("alice", "secret")].into_iter().collect();
+        creds.write_to_file(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parent_dir_of_a_bare_file_name_is_the_current_directory() {
+        assert_eq!(parent_dir(Path::new("creds")), Path::new("."));
+        assert_eq!(parent_dir(Path::new("dir/creds")), Path::new("dir"));
+        assert_eq!(parent_dir(Path::new("/creds")), Path::new("/"));
+    }
+}
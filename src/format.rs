@@ -0,0 +1,1432 @@
+//! Options for reading and writing the line-based credential format.
+//!
+//! The format is one `<username>:<password>` pair per line. Blank lines and
+//! comment lines starting with `#` are ignored when reading. The separator can
+//! be changed from `:` with [`ReadOptions::delimiter`] and
+//! [`WriteOptions::delimiter`], and the comment character with
+//! [`ReadOptions::comment`] and [`WriteOptions::comment`]. Fields can be
+//! escaped so that they may contain any character; see [`Escaping`].
+
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    mem,
+    path::Path,
+};
+
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{
+    creds::{Creds, CredsError, Redacted},
+    file,
+    integrity::{checksum_line, ChecksumReader, HashingWriter},
+    meta::CredMeta,
+    stream::{CredsStreamReader, CredsStreamWriter},
+};
+
+/// The default separator between usernames and passwords.
+pub const DEFAULT_DELIMITER: char = ':';
+
+/// The default character that starts a comment line.
+pub const DEFAULT_COMMENT: char = '#';
+
+/// The default maximum length of a line, in bytes.
+pub const DEFAULT_MAX_LINE_LEN: usize = 64 * 1024;
+
+/// The unescaped username and password of an entry.
+type Fields<'a> = (Cow<'a, str>, Cow<'a, str>);
+
+/// Options for reading a credential store.
+///
+/// Created by [`Creds::reader`]. The default options read the same format as
+/// [`Creds::read`].
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    delimiter: char,
+    comment: char,
+    strict: bool,
+    escaping: Escaping,
+    trim_whitespace: bool,
+    max_line_len: usize,
+    capacity: usize,
+    duplicates: DuplicatePolicy,
+    verify_checksum: bool,
+    metadata: bool,
+}
+
+impl ReadOptions {
+    /// Create the default set of read options.
+    pub fn new() -> Self {
+        Self {
+            delimiter: DEFAULT_DELIMITER,
+            comment: DEFAULT_COMMENT,
+            strict: false,
+            escaping: Escaping::None,
+            trim_whitespace: false,
+            max_line_len: DEFAULT_MAX_LINE_LEN,
+            capacity: 0,
+            duplicates: DuplicatePolicy::KeepLast,
+            verify_checksum: false,
+            metadata: false,
+        }
+    }
+
+    /// Sets the character separating usernames from passwords.
+    ///
+    /// Defaults to `:`. Accepts either a `char` or an ASCII byte like `b'\t'`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the delimiter is a newline.
+    pub fn delimiter(mut self, delimiter: impl Into<char>) -> Self {
+        self.delimiter = check_delimiter(delimiter.into());
+        self
+    }
+
+    /// Sets the character that starts a comment line, when it is the first
+    /// non-whitespace character of the line.
+    ///
+    /// Defaults to `#`. Accepts either a `char` or an ASCII byte like `b';'`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the comment character is a newline.
+    pub fn comment(mut self, comment: impl Into<char>) -> Self {
+        self.comment = check_comment(comment.into());
+        self
+    }
+
+    /// Sets whether [malformed](Line::Malformed) lines are rejected with
+    /// [`CredsError::MalformedLine`] instead of skipped.
+    ///
+    /// Defaults to `false`. See also [`Creds::read_strict`].
+    ///
+    /// Entries with invalid escape sequences are also treated as malformed.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets how escaped fields are decoded.
+    ///
+    /// Defaults to [`Escaping::None`]. This should match the escaping the file
+    /// was written with.
+    pub fn escaping(mut self, escaping: Escaping) -> Self {
+        self.escaping = escaping;
+        self
+    }
+
+    /// Sets whether leading and trailing whitespace is removed from usernames
+    /// and passwords.
+    ///
+    /// Defaults to `false`, which keeps whitespace around the delimiter as
+    /// part of the fields, so that passwords may start or end with spaces.
+    /// Enable this for hand-edited files written as `alice : hunter2`. The
+    /// fields are trimmed before they are unescaped, so escaped whitespace,
+    /// like `%20` with [`Escaping::Percent`], is kept.
+    ///
+    /// Line endings are removed either way.
+    pub fn trim_whitespace(mut self, trim_whitespace: bool) -> Self {
+        self.trim_whitespace = trim_whitespace;
+        self
+    }
+
+    /// Sets the maximum length of a line, in bytes, not counting the line
+    /// ending.
+    ///
+    /// Defaults to [`DEFAULT_MAX_LINE_LEN`], 64 KiB. Reading fails with
+    /// [`CredsError::LineTooLong`] as soon as a longer line is found, so that
+    /// input without newlines cannot make the reader allocate without bound.
+    pub fn max_line_len(mut self, max_line_len: usize) -> Self {
+        self.max_line_len = max_line_len;
+        self
+    }
+
+    /// Sets what happens when a username appears on more than one line.
+    ///
+    /// Defaults to [`DuplicatePolicy::KeepLast`].
+    pub fn duplicates(mut self, duplicates: DuplicatePolicy) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Sets whether a [checksum line](WriteOptions::checksum) at the end of
+    /// the input is verified.
+    ///
+    /// Defaults to `false`. If enabled and the last line is a checksum line,
+    /// reading fails with [`CredsError::ChecksumMismatch`] unless it matches
+    /// the lines before it. Input without a checksum line is read as usual.
+    /// Only affects [`ReadOptions::read`] and the functions built on it.
+    pub fn verify_checksum(mut self, verify_checksum: bool) -> Self {
+        self.verify_checksum = verify_checksum;
+        self
+    }
+
+    /// Sets whether [metadata](crate::meta) lines are read.
+    ///
+    /// Defaults to `false`, which skips them like any other comment, so that
+    /// credentials read from the file have no metadata at all. **This
+    /// includes the expiry time:** a credential that was written with
+    /// [an expiry time](crate::creds::Creds::insert_with_expiry) verifies
+    /// again when read without metadata. If enabled, a metadata line gives
+    /// all the metadata of the entry on the next line.
+    pub fn metadata(mut self, metadata: bool) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets how many credentials to allocate room for before reading, to
+    /// avoid reallocating while reading a large file.
+    ///
+    /// Defaults to 0. This is only a hint: any number of credentials can be
+    /// read either way. See [`Creds::with_capacity`].
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Classifies a single line according to these options.
+    ///
+    /// The line should not include its line ending. The fields of an entry are
+    /// returned as they appear in the line, without unescaping.
+    pub fn parse_line<'a>(&self, line: &'a str) -> Line<'a> {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            Line::Blank
+        } else if trimmed.starts_with(self.comment) {
+            Line::Comment(line)
+        } else if let Some((username, password)) = self.escaping.split(line, self.delimiter) {
+            Line::Entry { username, password }
+        } else {
+            Line::Malformed
+        }
+    }
+
+    /// Parses a credential store from the given reader.
+    ///
+    /// I/O errors from the reader are reported as [`CredsError::ReadLine`],
+    /// with the 1-based number of the line that was being read.
+    pub fn read(&self, mut reader: impl BufRead) -> Result<Creds, CredsError> {
+        let mut creds = self.new_creds();
+        let mut lines = self.line_reader();
+        let mut checksum = self.checksum_reader();
+        let mut pending_meta = None;
+        while let Some((line_number, line, ending)) = lines.next_line(&mut reader)? {
+            if let Some(checksum) = &mut checksum {
+                checksum.update(&line, ending);
+            }
+            self.read_line(&mut creds, &mut pending_meta, line_number, &line)?;
+        }
+        checksum.map_or(Ok(()), ChecksumReader::finish)?;
+        Ok(creds)
+    }
+
+    /// Returns a [`CredsStreamReader`] that parses entries one at a time
+    /// with these options.
+    pub fn stream<R: BufRead>(&self, reader: R) -> CredsStreamReader<R> {
+        CredsStreamReader::with_options(reader, self.clone())
+    }
+
+    /// Returns a [`ChecksumReader`], if checksums are verified.
+    pub(crate) fn checksum_reader(&self) -> Option<ChecksumReader> {
+        self.verify_checksum
+            .then(|| ChecksumReader::new(self.comment))
+    }
+
+    /// Returns an empty store with the configured capacity.
+    pub(crate) fn new_creds(&self) -> Creds {
+        let mut creds = Creds::with_capacity(self.capacity);
+        creds.set_delimiter(self.delimiter);
+        creds
+    }
+
+    /// Returns a [`LineReader`] for the configured maximum line length.
+    pub(crate) fn line_reader(&self) -> LineReader {
+        LineReader::new(self.max_line_len)
+    }
+
+    /// Adds the entry on a single line, without its line ending, to `creds`.
+    ///
+    /// This is shared by the sync and async readers, so that they parse
+    /// identically.
+    ///
+    /// `pending_meta` holds the metadata read from the previous line, if it
+    /// was a [metadata](ReadOptions::metadata) line.
+    pub(crate) fn read_line(
+        &self,
+        creds: &mut Creds,
+        pending_meta: &mut Option<CredMeta>,
+        line_number: usize,
+        line: &str,
+    ) -> Result<(), CredsError> {
+        if let Some(meta) = self.parse_meta(line) {
+            *pending_meta = Some(meta);
+            return Ok(());
+        }
+        let meta = pending_meta.take().unwrap_or_default();
+        let (username, password) = match self.parse_entry(line_number, line)? {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        match self.duplicates {
+            DuplicatePolicy::KeepFirst if creds.contains(&username) => {
+                zeroize_field(password);
+                return Ok(());
+            }
+            DuplicatePolicy::Error if creds.contains(&username) => {
+                zeroize_field(password);
+                return Err(CredsError::DuplicateUsername {
+                    line_number,
+                    username: username.into_owned(),
+                });
+            }
+            _ => {}
+        }
+        creds.insert_read(username.into_owned(), password.into_owned(), meta);
+        Ok(())
+    }
+
+    /// Parses a [metadata](ReadOptions::metadata) line, returning the
+    /// metadata it gives the entry on the next line, or `None` if it is not
+    /// one or metadata is not read.
+    pub(crate) fn parse_meta(&self, line: &str) -> Option<CredMeta> {
+        if !self.metadata {
+            return None;
+        }
+        CredMeta::parse_line(line, self.comment)
+    }
+
+    /// Parses and unescapes the entry on a single line, without its line
+    /// ending.
+    ///
+    /// Returns `None` for blank lines and comments, and for malformed lines
+    /// unless reading is strict.
+    pub(crate) fn parse_entry<'a>(
+        &self,
+        line_number: usize,
+        line: &'a str,
+    ) -> Result<Option<Fields<'a>>, CredsError> {
+        let entry = match self.parse_line(line) {
+            Line::Entry { username, password } if self.trim_whitespace => self
+                .escaping
+                .unescape(username.trim(), self.delimiter, self.comment)
+                .zip(
+                    self.escaping
+                        .unescape(password.trim(), self.delimiter, self.comment),
+                ),
+            Line::Entry { username, password } => self
+                .escaping
+                .unescape(username, self.delimiter, self.comment)
+                .zip(
+                    self.escaping
+                        .unescape(password, self.delimiter, self.comment),
+                ),
+            Line::Blank | Line::Comment(_) => return Ok(None),
+            Line::Malformed => None,
+        };
+        match entry {
+            Some(entry) => Ok(Some(entry)),
+            None if self.strict => Err(CredsError::MalformedLine { line_number }),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns `true` if the first line for a username takes effect, rather
+    /// than the last.
+    pub(crate) fn keeps_first_duplicate(&self) -> bool {
+        self.duplicates == DuplicatePolicy::KeepFirst
+    }
+
+    /// Returns write options for the same format.
+    pub(crate) fn write_options(&self) -> WriteOptions {
+        WriteOptions::new()
+            .delimiter(self.delimiter)
+            .comment(self.comment)
+            .escaping(self.escaping)
+            .metadata(self.metadata)
+    }
+
+    /// Parses a credential store from the given file.
+    ///
+    /// See also: [`ReadOptions::read`]
+    pub fn read_from_file(&self, path: impl AsRef<Path>) -> Result<Creds, CredsError> {
+        let file = File::open(path)?;
+        self.read(BufReader::new(file))
+    }
+
+    /// Finds the password for a single username in the given reader, without
+    /// reading the other credentials into memory.
+    ///
+    /// This scans the input line by line, so every lookup takes time
+    /// proportional to the size of the input; to look up many usernames, read
+    /// the whole store with [`ReadOptions::read`] instead. The result is the
+    /// same as reading the store and calling [`Creds::get`], but only lines
+    /// for the given username are checked for
+    /// [duplicates](ReadOptions::duplicates). With
+    /// [`DuplicatePolicy::KeepFirst`], the scan stops at the first match;
+    /// otherwise, it continues to the end of the input.
+    pub fn lookup(
+        &self,
+        mut reader: impl BufRead,
+        username: &str,
+    ) -> Result<Option<String>, CredsError> {
+        let mut found: Option<String> = None;
+        let mut lines = self.line_reader();
+        while let Some((line_number, line, _)) = lines.next_line(&mut reader)? {
+            let (entry_username, password) = match self.parse_entry(line_number, &line)? {
+                Some(entry) if entry.0 == username => entry,
+                Some((_, password)) => {
+                    zeroize_field(password);
+                    continue;
+                }
+                None => continue,
+            };
+            if let Some(mut previous) = found.take() {
+                previous.zeroize();
+                if self.duplicates == DuplicatePolicy::Error {
+                    zeroize_field(password);
+                    return Err(CredsError::DuplicateUsername {
+                        line_number,
+                        username: entry_username.into_owned(),
+                    });
+                }
+            }
+            found = Some(password.into_owned());
+            if self.duplicates == DuplicatePolicy::KeepFirst {
+                break;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Finds the password for a single username in the given file, without
+    /// reading the other credentials into memory.
+    ///
+    /// See also: [`ReadOptions::lookup`]
+    pub fn lookup_in_file(
+        &self,
+        path: impl AsRef<Path>,
+        username: &str,
+    ) -> Result<Option<String>, CredsError> {
+        let file = File::open(path)?;
+        self.lookup(BufReader::new(file), username)
+    }
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Options for writing a credential store.
+///
+/// Created by [`Creds::writer`]. The default options write the same format as
+/// [`Creds::write`].
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    delimiter: char,
+    comment: char,
+    escaping: Escaping,
+    sort_keys: bool,
+    checksum: bool,
+    metadata: bool,
+}
+
+impl WriteOptions {
+    /// Create the default set of write options.
+    pub fn new() -> Self {
+        Self {
+            delimiter: DEFAULT_DELIMITER,
+            comment: DEFAULT_COMMENT,
+            escaping: Escaping::None,
+            sort_keys: false,
+            checksum: false,
+            metadata: false,
+        }
+    }
+
+    /// Sets the character separating usernames from passwords.
+    ///
+    /// Defaults to `:`. Accepts either a `char` or an ASCII byte like `b'\t'`.
+    ///
+    /// Usernames may not contain the delimiter. Passwords may, since lines are
+    /// split at the first delimiter when reading.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the delimiter is a newline.
+    pub fn delimiter(mut self, delimiter: impl Into<char>) -> Self {
+        self.delimiter = check_delimiter(delimiter.into());
+        self
+    }
+
+    /// Sets the comment character of the file, so that no username is
+    /// written that would be read back as a comment.
+    ///
+    /// Defaults to `#`. This should match the
+    /// [comment character](ReadOptions::comment) the file is read with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the comment character is a newline.
+    pub fn comment(mut self, comment: impl Into<char>) -> Self {
+        self.comment = check_comment(comment.into());
+        self
+    }
+
+    /// Sets how fields are escaped.
+    ///
+    /// Defaults to [`Escaping::None`]. Files written with escaping must be
+    /// read with the same escaping.
+    pub fn escaping(mut self, escaping: Escaping) -> Self {
+        self.escaping = escaping;
+        self
+    }
+
+    /// Sets whether entries are written sorted by username, instead of in
+    /// insertion order.
+    ///
+    /// Defaults to `false`. Sorting gives the same output for the same
+    /// credentials regardless of the order they were inserted in, which keeps
+    /// diffs small when the file is kept in version control. Usernames are
+    /// compared by their UTF-8 bytes. The order of the store itself is not
+    /// changed.
+    pub fn sort_keys(mut self, sort_keys: bool) -> Self {
+        self.sort_keys = sort_keys;
+        self
+    }
+
+    /// Sets whether a checksum line is written after the entries.
+    ///
+    /// Defaults to `false`. The checksum line is a comment like
+    /// `#sha256:<hex>`, using the [comment character](WriteOptions::comment),
+    /// with the SHA-256 hash of all the lines before it. Readers skip it, and
+    /// [`ReadOptions::verify_checksum`] checks it, to detect a truncated or
+    /// corrupted file. It does not protect against deliberate changes; see
+    /// [`Creds::write_with_hmac`] for that.
+    ///
+    /// [`WriteOptions::append_to_file`] never writes a checksum, since it
+    /// would not cover the existing lines.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Sets whether [metadata](crate::meta) lines are written.
+    ///
+    /// Defaults to `false`, which writes no metadata at all. **This includes
+    /// the expiry time:** a credential inserted with
+    /// [`Creds::insert_with_expiry`](crate::creds::Creds::insert_with_expiry)
+    /// is written like any other, and verifies again once the file is read
+    /// back. If enabled, each entry that has metadata is preceded by a
+    /// metadata comment line; read it back with [`ReadOptions::metadata`].
+    pub fn metadata(mut self, metadata: bool) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Writes the credentials into the given writer, in insertion order or
+    /// [sorted](WriteOptions::sort_keys).
+    ///
+    /// Without escaping, returns [`CredsError::IllegalCharacter`] if a
+    /// username contains the delimiter or a line break (`\n` or `\r`) or
+    /// would be read back as a comment, or if a password contains a line
+    /// break. A `\r` would be taken for part of a line ending, or trimmed
+    /// with [`ReadOptions::trim_whitespace`], so it could not be read back.
+    pub fn write(&self, creds: &Creds, writer: impl Write) -> Result<(), CredsError> {
+        if self.sort_keys {
+            self.write_sorted_by(creds, writer, str::cmp)
+        } else {
+            self.write_entries(creds.iter_meta(), writer)
+        }
+    }
+
+    /// Writes the credentials into the given writer, sorted by username with
+    /// the given comparison function.
+    ///
+    /// This ignores [`WriteOptions::sort_keys`]. Entries that compare equal
+    /// are written in insertion order. The order of the store itself is not
+    /// changed.
+    pub fn write_sorted_by(
+        &self,
+        creds: &Creds,
+        writer: impl Write,
+        mut compare: impl FnMut(&str, &str) -> Ordering,
+    ) -> Result<(), CredsError> {
+        let mut entries: Vec<_> = creds.iter_meta().collect();
+        entries.sort_by(|&(a, _, _), &(b, _, _)| compare(a, b));
+        self.write_entries(entries, writer)
+    }
+
+    fn write_entries<'a>(
+        &self,
+        entries: impl IntoIterator<Item = (&'a str, &'a str, &'a CredMeta)>,
+        mut writer: impl Write,
+    ) -> Result<(), CredsError> {
+        if !self.checksum {
+            return self.write_lines(entries, writer);
+        }
+        let mut hashing = HashingWriter::new(&mut writer);
+        self.write_lines(entries, &mut hashing)?;
+        let line = checksum_line(self.comment, hashing.finish());
+        writer.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    fn write_lines<'a>(
+        &self,
+        entries: impl IntoIterator<Item = (&'a str, &'a str, &'a CredMeta)>,
+        mut writer: impl Write,
+    ) -> Result<(), CredsError> {
+        for (username, password, meta) in entries {
+            if let Some(line) = self.meta_line(meta) {
+                writer.write_all(line.as_bytes())?;
+                writer.write_all(b"\n")?;
+            }
+            self.write_entry(&mut writer, username, password)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the part of the given metadata that is written.
+    pub(crate) fn written_meta(&self, meta: &CredMeta) -> CredMeta {
+        if self.metadata {
+            meta.clone()
+        } else {
+            CredMeta::default()
+        }
+    }
+
+    /// Returns the metadata line written before an entry with the given
+    /// metadata, without a line ending, or `None` if there is nothing to
+    /// write.
+    pub(crate) fn meta_line(&self, meta: &CredMeta) -> Option<String> {
+        let meta = self.written_meta(meta);
+        (!meta.is_empty()).then(|| meta.to_line(self.comment))
+    }
+
+    /// Returns a digest for the checksum line, if one is written.
+    pub(crate) fn checksum_digest(&self) -> Option<Sha256> {
+        self.checksum.then(Sha256::new)
+    }
+
+    /// Returns the checksum line for contents with the given hash.
+    pub(crate) fn checksum_line(&self, digest: Sha256) -> String {
+        checksum_line(self.comment, digest)
+    }
+
+    /// Returns a [`CredsStreamWriter`] that writes entries one at a time with
+    /// these options.
+    pub fn stream_writer<W: Write>(&self, writer: W) -> CredsStreamWriter<W> {
+        CredsStreamWriter::with_options(writer, self.clone())
+    }
+
+    /// Writes a single entry, without a line ending.
+    pub(crate) fn write_entry(
+        &self,
+        mut writer: impl Write,
+        username: &str,
+        password: &str,
+    ) -> Result<(), CredsError> {
+        if self.escaping == Escaping::None
+            && (username.contains([self.delimiter, '\n', '\r'])
+                || username.trim_start().starts_with(self.comment)
+                || password.contains(['\n', '\r']))
+        {
+            return Err(CredsError::IllegalCharacter {
+                username: username.to_owned(),
+            });
+        }
+        let username = self.escaping.escape(username, self.delimiter, self.comment);
+        let password = self.escaping.escape(password, self.delimiter, self.comment);
+        let result = write!(writer, "{}{}{}", username, self.delimiter, password);
+        if let Cow::Owned(mut escaped) = password {
+            escaped.zeroize();
+        }
+        Ok(result?)
+    }
+
+    /// Writes the credential store to the given file.
+    ///
+    /// The file is replaced atomically: the credentials are written to a
+    /// temporary file in the same directory, which is then renamed over the
+    /// destination. If the process crashes or writing fails, the destination
+    /// keeps its old contents.
+    ///
+    /// An existing file keeps its permissions. A new file is given mode `0600`
+    /// on Unix, so that other users cannot read the credentials; on other
+    /// platforms, it gets the default permissions.
+    ///
+    /// See also: [`WriteOptions::write`]
+    pub fn write_to_file(&self, creds: &Creds, path: impl AsRef<Path>) -> Result<(), CredsError> {
+        file::write_atomic(path.as_ref(), |writer| self.write(creds, writer))
+    }
+
+    /// Appends the credentials to the end of the given file, creating it if
+    /// it does not exist.
+    ///
+    /// This is much faster than rewriting the whole file when adding a few
+    /// credentials to a large one, but the file is not replaced atomically,
+    /// and a username that is already in the file is written again rather
+    /// than updated. Reading the file gives the last password written for
+    /// each username, so the result is the same as replacing the entries, but
+    /// the file keeps growing; rewrite it with
+    /// [`write_to_file`](WriteOptions::write_to_file) from time to time to
+    /// compact it.
+    ///
+    /// See also: [`WriteOptions::write`]
+    pub fn append_to_file(&self, creds: &Creds, path: impl AsRef<Path>) -> Result<(), CredsError> {
+        let options = self.clone().checksum(false);
+        file::append(path.as_ref(), |writer| options.write(creds, writer))
+    }
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A format of credential files, as detected by [`FileFormat::detect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// The line format, with plaintext passwords, hashes like those in an
+    /// Apache `.htpasswd` file, or a mix of both.
+    Lines,
+    /// A JSON object mapping usernames to passwords.
+    Json,
+}
+
+impl FileFormat {
+    /// Guesses the format of the given file contents.
+    ///
+    /// The contents are [`FileFormat::Json`] if they are a JSON object. With
+    /// the `json` feature, this is checked by parsing them; without it, they
+    /// only need to start with `{` and end with `}`, ignoring whitespace.
+    ///
+    /// Otherwise, blank lines and `#` comments at the start are skipped, and
+    /// the first remaining line should be a `<username>:<password>` entry,
+    /// for [`FileFormat::Lines`]. This includes entries whose username starts
+    /// with `{`, such as `{admin}:hunter2`. Empty contents are
+    /// [`FileFormat::Lines`] as well.
+    ///
+    /// Returns `None` if the contents are not valid UTF-8 or are neither.
+    pub fn detect(contents: &[u8]) -> Option<Self> {
+        let contents = std::str::from_utf8(contents).ok()?;
+        if is_json_object(contents) {
+            return Some(Self::Json);
+        }
+        let options = ReadOptions::new();
+        for line in contents.lines() {
+            match options.parse_line(line) {
+                Line::Blank | Line::Comment(_) => {}
+                Line::Entry { .. } => return Some(Self::Lines),
+                Line::Malformed => return None,
+            }
+        }
+        Some(Self::Lines)
+    }
+}
+
+/// Returns `true` if the given contents are a JSON object.
+#[cfg(feature = "json")]
+fn is_json_object(contents: &str) -> bool {
+    contents.trim_start().starts_with('{')
+        && serde_json::from_str::<serde::de::IgnoredAny>(contents).is_ok()
+}
+
+/// Returns `true` if the given contents look like a JSON object.
+#[cfg(not(feature = "json"))]
+fn is_json_object(contents: &str) -> bool {
+    let contents = contents.trim();
+    contents.starts_with('{') && contents.ends_with('}')
+}
+
+/// What happens when reading a username that already appeared on an earlier
+/// line, as set by [`ReadOptions::duplicates`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// The password on the last line wins, like with [`Creds::insert`]. The
+    /// entry keeps the position of the first line.
+    #[default]
+    KeepLast,
+    /// The password on the first line wins, and later lines are ignored.
+    KeepFirst,
+    /// Reading fails with [`CredsError::DuplicateUsername`].
+    Error,
+}
+
+/// How usernames and passwords are escaped in the line format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Escaping {
+    /// Fields are written as-is, so characters that would break the format are
+    /// rejected when writing.
+    #[default]
+    None,
+    /// Percent-encoding, as in URLs.
+    ///
+    /// The delimiter, the comment character, `%`, and line-ending characters
+    /// are written as `%` followed by the two hex digits of each of their
+    /// UTF-8 bytes, so arbitrary strings round-trip losslessly. When reading,
+    /// any `%XX` sequence is decoded.
+    Percent,
+    /// Backslash escapes.
+    ///
+    /// The delimiter, the comment character, and `\` are written with a
+    /// leading `\`, and line feeds and carriage returns are written as `\n`
+    /// and `\r`, so arbitrary strings round-trip losslessly.
+    ///
+    /// When reading, a delimiter preceded by a backslash does not separate the
+    /// username from the password. Backslashes that do not start one of the
+    /// escapes above are kept as they are, so files written without escaping
+    /// mostly read back the same; the exception is a literal `\` followed by
+    /// one of the escaped characters, such as `\\` or `\n`.
+    Backslash,
+}
+
+impl Escaping {
+    /// Splits a line at the first delimiter that is not escaped.
+    fn split<'a>(&self, line: &'a str, delimiter: char) -> Option<(&'a str, &'a str)> {
+        match self {
+            Self::None | Self::Percent => line.split_once(delimiter),
+            Self::Backslash => {
+                let mut escaped = false;
+                for (index, c) in line.char_indices() {
+                    if escaped {
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == delimiter {
+                        return Some((&line[..index], &line[index + c.len_utf8()..]));
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn escape<'a>(&self, field: &'a str, delimiter: char, comment: char) -> Cow<'a, str> {
+        match self {
+            Self::None => Cow::Borrowed(field),
+            Self::Backslash => {
+                let needs_escape =
+                    |c: char| c == delimiter || c == comment || matches!(c, '\\' | '\n' | '\r');
+                if !field.contains(needs_escape) {
+                    return Cow::Borrowed(field);
+                }
+                let mut escaped = String::with_capacity(field.len());
+                for c in field.chars() {
+                    match c {
+                        '\n' => escaped.push_str("\\n"),
+                        '\r' => escaped.push_str("\\r"),
+                        c if needs_escape(c) => {
+                            escaped.push('\\');
+                            escaped.push(c);
+                        }
+                        c => escaped.push(c),
+                    }
+                }
+                Cow::Owned(escaped)
+            }
+            Self::Percent => {
+                let needs_escape =
+                    |c: char| c == delimiter || c == comment || matches!(c, '%' | '\n' | '\r');
+                if !field.contains(needs_escape) {
+                    return Cow::Borrowed(field);
+                }
+                let mut escaped = String::with_capacity(field.len());
+                for c in field.chars() {
+                    if needs_escape(c) {
+                        let mut buf = [0; 4];
+                        for byte in c.encode_utf8(&mut buf).bytes() {
+                            escaped.push_str(&format!("%{:02X}", byte));
+                        }
+                    } else {
+                        escaped.push(c);
+                    }
+                }
+                Cow::Owned(escaped)
+            }
+        }
+    }
+
+    /// Reverses [`Escaping::escape`], returning `None` for invalid escapes.
+    fn unescape<'a>(&self, field: &'a str, delimiter: char, comment: char) -> Option<Cow<'a, str>> {
+        match self {
+            Self::None => Some(Cow::Borrowed(field)),
+            Self::Backslash => {
+                if !field.contains('\\') {
+                    return Some(Cow::Borrowed(field));
+                }
+                let mut unescaped = String::with_capacity(field.len());
+                let mut chars = field.chars().peekable();
+                while let Some(c) = chars.next() {
+                    if c != '\\' {
+                        unescaped.push(c);
+                        continue;
+                    }
+                    match chars.peek() {
+                        Some('n') => unescaped.push('\n'),
+                        Some('r') => unescaped.push('\r'),
+                        Some(&next) if next == delimiter || next == comment || next == '\\' => {
+                            unescaped.push(next)
+                        }
+                        _ => {
+                            unescaped.push('\\');
+                            continue;
+                        }
+                    }
+                    chars.next();
+                }
+                Some(Cow::Owned(unescaped))
+            }
+            Self::Percent => {
+                if !field.contains('%') {
+                    return Some(Cow::Borrowed(field));
+                }
+                let mut bytes = Vec::with_capacity(field.len());
+                let mut rest = field.as_bytes();
+                while let Some((&byte, tail)) = rest.split_first() {
+                    if byte == b'%' {
+                        let hex = tail.get(..2)?;
+                        if !hex.iter().all(u8::is_ascii_hexdigit) {
+                            return None;
+                        }
+                        let hex = std::str::from_utf8(hex).ok()?;
+                        bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                        rest = &tail[2..];
+                    } else {
+                        bytes.push(byte);
+                        rest = tail;
+                    }
+                }
+                String::from_utf8(bytes).ok().map(Cow::Owned)
+            }
+        }
+    }
+}
+
+/// Zeroes out a parsed password, if unescaping copied it.
+pub(crate) fn zeroize_field(field: Cow<'_, str>) {
+    if let Cow::Owned(mut field) = field {
+        field.zeroize();
+    }
+}
+
+pub(crate) fn check_delimiter(delimiter: char) -> char {
+    assert_ne!(delimiter, '\n', "the delimiter cannot be a newline");
+    delimiter
+}
+
+fn check_comment(comment: char) -> char {
+    assert_ne!(comment, '\n', "the comment character cannot be a newline");
+    comment
+}
+
+/// A single line of a credentials file, as classified by [`Line::parse`] or
+/// [`ReadOptions::parse_line`].
+///
+/// The [`Debug`](fmt::Debug) output redacts the password of an entry.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// An empty line, or one containing only whitespace.
+    Blank,
+    /// A comment line, whose first non-whitespace character is the comment
+    /// character, `#` by default.
+    ///
+    /// This holds the entire line, including any leading whitespace and the
+    /// comment character itself, so that it can be written back unchanged.
+    Comment(&'a str),
+    /// A `<username>:<password>` credential line.
+    ///
+    /// The line is split at the first delimiter, so the password may itself
+    /// contain the delimiter.
+    Entry {
+        /// The text before the first delimiter.
+        username: &'a str,
+        /// The text after the first delimiter.
+        password: &'a str,
+    },
+    /// Any other line, which has no delimiter.
+    Malformed,
+}
+
+impl<'a> Line<'a> {
+    /// Classifies a single line using the default `:` delimiter.
+    ///
+    /// The line should not include its line ending.
+    pub fn parse(line: &'a str) -> Self {
+        ReadOptions::new().parse_line(line)
+    }
+}
+
+impl fmt::Debug for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Blank => f.write_str("Blank"),
+            Self::Comment(comment) => f.debug_tuple("Comment").field(comment).finish(),
+            Self::Entry { username, .. } => f
+                .debug_struct("Entry")
+                .field("username", username)
+                .field("password", &Redacted)
+                .finish(),
+            Self::Malformed => f.write_str("Malformed"),
+        }
+    }
+}
+
+/// Splits a reader into lines, like [`BufRead::lines`], but with a maximum
+/// line length.
+///
+/// Lines may end with either `\n` or `\r\n`, and the line ending is not part
+/// of the line.
+///
+/// The sync and async readers both feed it bytes from their buffers, so that
+/// they split lines identically. The lines are zeroed out when dropped, since
+/// they contain passwords.
+pub(crate) struct LineReader {
+    line: Zeroizing<Vec<u8>>,
+    line_number: usize,
+    /// The offset of the current line from the start of the input.
+    offset: usize,
+    max_line_len: usize,
+}
+
+impl LineReader {
+    fn new(max_line_len: usize) -> Self {
+        Self {
+            line: Zeroizing::new(Vec::new()),
+            line_number: 1,
+            offset: 0,
+            max_line_len,
+        }
+    }
+
+    /// Reads the next line, its 1-based number, and its line ending, or
+    /// `None` at the end of the input.
+    pub(crate) fn next_line(
+        &mut self,
+        reader: &mut impl BufRead,
+    ) -> Result<Option<(usize, Zeroizing<String>, LineEnding)>, CredsError> {
+        loop {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(self.error(error)),
+            };
+            let (consumed, complete) = self.feed(available)?;
+            reader.consume(consumed);
+            if complete {
+                return self.take_line();
+            }
+        }
+    }
+
+    /// Appends bytes from the reader's buffer to the current line, up to and
+    /// including the first newline.
+    ///
+    /// Returns how many bytes were used, and whether the line is complete.
+    /// Empty input marks the end of the input, which completes any partial
+    /// line.
+    pub(crate) fn feed(&mut self, available: &[u8]) -> Result<(usize, bool), CredsError> {
+        let (consumed, complete) = match available.iter().position(|&byte| byte == b'\n') {
+            Some(index) => (index + 1, true),
+            None => (available.len(), available.is_empty()),
+        };
+        self.line.extend_from_slice(&available[..consumed]);
+        // Leave room for a `\r\n` line ending.
+        if self.line.len() > self.max_line_len.saturating_add(2) {
+            return Err(CredsError::LineTooLong {
+                line_number: self.line_number,
+                max_len: self.max_line_len,
+            });
+        }
+        Ok((consumed, complete))
+    }
+
+    /// Takes the line completed by [`LineReader::feed`], separately from its
+    /// line ending, or returns `None` if the input ended without a partial
+    /// line.
+    pub(crate) fn take_line(
+        &mut self,
+    ) -> Result<Option<(usize, Zeroizing<String>, LineEnding)>, CredsError> {
+        if self.line.is_empty() {
+            return Ok(None);
+        }
+        let mut line = mem::take(&mut *self.line);
+        let len = line.len();
+        let mut ending = LineEnding::None;
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            ending = LineEnding::Lf;
+        }
+        // Also trim a `\r` at the end of the input, so that a file saved with
+        // CRLF line endings reads the same whether or not its last line ends
+        // with a newline.
+        if line.last() == Some(&b'\r') {
+            line.pop();
+            ending = match ending {
+                LineEnding::Lf => LineEnding::CrLf,
+                _ => LineEnding::Cr,
+            };
+        }
+        if line.len() > self.max_line_len {
+            line.zeroize();
+            return Err(CredsError::LineTooLong {
+                line_number: self.line_number,
+                max_len: self.max_line_len,
+            });
+        }
+        let line = String::from_utf8(line).map_err(|error| {
+            let offset = self.offset + error.utf8_error().valid_up_to();
+            error.into_bytes().zeroize();
+            CredsError::InvalidUtf8 {
+                line_number: self.line_number,
+                offset,
+            }
+        })?;
+        let line_number = self.line_number;
+        self.line_number += 1;
+        self.offset += len;
+        Ok(Some((line_number, Zeroizing::new(line), ending)))
+    }
+
+    /// Attaches the number of the line being read to an I/O error.
+    pub(crate) fn error(&self, source: io::Error) -> CredsError {
+        CredsError::ReadLine {
+            line_number: self.line_number,
+            source,
+        }
+    }
+}
+
+/// The line ending that [`LineReader`] removed from a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LineEnding {
+    /// The last line of the input, without a line ending.
+    None,
+    Lf,
+    CrLf,
+    /// A carriage return at the end of the input.
+    Cr,
+}
+
+impl LineEnding {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_lines() {
+        assert_eq!(Line::parse(""), Line::Blank);
+        assert_eq!(Line::parse(" \t"), Line::Blank);
+        assert_eq!(Line::parse("# alice:a"), Line::Comment("# alice:a"));
+        assert_eq!(Line::parse("  # indented"), Line::Comment("  # indented"));
+        assert_eq!(
+            Line::parse("alice:a:b"),
+            Line::Entry {
+                username: "alice",
+                password: "a:b"
+            }
+        );
+        assert_eq!(Line::parse("alice=a"), Line::Malformed);
+    }
+
+    fn entries(creds: &Creds) -> Vec<(&str, &str)> {
+        creds.iter().collect()
+    }
+
+    fn round_trip(creds: &Creds, escaping: Escaping) -> (String, Creds) {
+        let mut contents = Vec::new();
+        WriteOptions::new()
+            .escaping(escaping)
+            .write(creds, &mut contents)
+            .unwrap();
+        let read = ReadOptions::new()
+            .escaping(escaping)
+            .strict(true)
+            .read(&contents[..])
+            .unwrap();
+        (String::from_utf8(contents).unwrap(), read)
+    }
+
+    #[test]
+    fn percent_escaping_round_trips() {
+        let mut creds = Creds::new();
+        creds.insert("user:name", "line1\nline2");
+        creds.insert("percent", "100%:sure\r\n");
+        creds.insert("#hash", "%41");
+        let (contents, read) = round_trip(&creds, Escaping::Percent);
+        assert_eq!(
+            contents,
+            "user%3Aname:line1%0Aline2\npercent:100%25%3Asure%0D%0A\n%23hash:%2541\n"
+        );
+        assert_eq!(entries(&read), entries(&creds));
+        assert!(matches!(
+            WriteOptions::new().write(&creds, Vec::new()),
+            Err(CredsError::IllegalCharacter { .. })
+        ));
+    }
+
+    #[test]
+    fn backslash_escaping_round_trips() {
+        let mut creds = Creds::new();
+        creds.insert("user:name", "line1\nline2");
+        creds.insert("back\\slash", "a\\:b\r");
+        let (contents, read) = round_trip(&creds, Escaping::Backslash);
+        assert_eq!(
+            contents,
+            "user\\:name:line1\\nline2\nback\\\\slash:a\\\\\\:b\\r\n"
+        );
+        assert_eq!(entries(&read), entries(&creds));
+    }
+
+    #[test]
+    fn backslash_escaping_reads_legacy_files() {
+        let read = ReadOptions::new()
+            .escaping(Escaping::Backslash)
+            .read("alice:C:\\Users\\x\n".as_bytes())
+            .unwrap();
+        assert_eq!(read.get("alice"), Some("C:\\Users\\x"));
+    }
+
+    #[test]
+    fn loads_only_real_entries() {
+        let contents = "# header\n\nalice:a\n  # note about bob\nbob:b\n\n# trailer\n";
+        let creds = Creds::read(contents.as_bytes()).unwrap();
+        assert_eq!(creds.usernames().collect::<Vec<_>>(), ["alice", "bob"]);
+    }
+
+    #[test]
+    fn comment_character_is_configurable() {
+        let contents = "; header\n#alice:a\n";
+        let creds = ReadOptions::new()
+            .comment(';')
+            .read(contents.as_bytes())
+            .unwrap();
+        assert_eq!(creds.usernames().collect::<Vec<_>>(), ["#alice"]);
+    }
+
+    #[test]
+    fn rejects_lines_longer_than_the_maximum() {
+        // An endless line, which would never fit in memory.
+        let endless = std::io::BufReader::new(std::io::repeat(b'a'));
+        let error = ReadOptions::new()
+            .max_line_len(1024)
+            .read(endless)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            CredsError::LineTooLong {
+                line_number: 1,
+                max_len: 1024
+            }
+        ));
+
+        let contents = format!("alice:a\nbob:{}\n", "b".repeat(64 * 1024));
+        let error = Creds::read(contents.as_bytes()).unwrap_err();
+        assert!(matches!(
+            error,
+            CredsError::LineTooLong { line_number: 2, .. }
+        ));
+        let contents = format!("alice:{}\n", "a".repeat(1000));
+        assert!(Creds::read(contents.as_bytes()).is_ok());
+    }
+
+    const DUPLICATED: &str = "alice:first\nbob:b\nalice:second\n";
+
+    fn read_duplicated(duplicates: DuplicatePolicy) -> Result<Creds, CredsError> {
+        ReadOptions::new()
+            .duplicates(duplicates)
+            .read(DUPLICATED.as_bytes())
+    }
+
+    #[test]
+    fn keep_last_lets_the_last_line_win() {
+        let creds = read_duplicated(DuplicatePolicy::KeepLast).unwrap();
+        assert_eq!(creds.len(), 2);
+        assert_eq!(creds.get("alice"), Some("second"));
+        assert_eq!(creds.get("bob"), Some("b"));
+    }
+
+    #[test]
+    fn keep_first_lets_the_first_line_win() {
+        let creds = read_duplicated(DuplicatePolicy::KeepFirst).unwrap();
+        assert_eq!(creds.len(), 2);
+        assert_eq!(creds.get("alice"), Some("first"));
+        assert_eq!(creds.get("bob"), Some("b"));
+    }
+
+    #[test]
+    fn error_policy_reports_the_repeated_line() {
+        let error = read_duplicated(DuplicatePolicy::Error).unwrap_err();
+        assert!(matches!(
+            error,
+            CredsError::DuplicateUsername { line_number: 3, ref username } if username == "alice"
+        ));
+        assert!(ReadOptions::new()
+            .duplicates(DuplicatePolicy::Error)
+            .read("alice:a\nbob:b\n".as_bytes())
+            .is_ok());
+    }
+
+    #[test]
+    fn lookup_follows_the_duplicate_policy() {
+        let lookup = |duplicates| {
+            ReadOptions::new()
+                .duplicates(duplicates)
+                .lookup(DUPLICATED.as_bytes(), "alice")
+        };
+        assert_eq!(
+            lookup(DuplicatePolicy::KeepLast).unwrap().as_deref(),
+            Some("second")
+        );
+        assert_eq!(
+            lookup(DuplicatePolicy::KeepFirst).unwrap().as_deref(),
+            Some("first")
+        );
+        assert!(matches!(
+            lookup(DuplicatePolicy::Error),
+            Err(CredsError::DuplicateUsername { line_number: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn crlf_endings_leave_no_carriage_returns() {
+        let creds = ReadOptions::new()
+            .read("alice:a b\r\nbob:b\r\ncarol:c".as_bytes())
+            .unwrap();
+        assert_eq!(creds.get("alice"), Some("a b"));
+        assert_eq!(creds.get("bob"), Some("b"));
+        assert_eq!(creds.get("carol"), Some("c"));
+        assert!(creds.iter().all(|(_, password)| !password.contains('\r')));
+    }
+
+    #[test]
+    fn carriage_returns_are_only_written_escaped() {
+        for (username, password) in [("alice", "pw\r"), ("alice", "p\rw"), ("ali\rce", "pw")] {
+            let creds: Creds = [(username, password)].into_iter().collect();
+            assert!(matches!(
+                WriteOptions::new().write(&creds, Vec::new()),
+                Err(CredsError::IllegalCharacter { .. })
+            ));
+            for escaping in [Escaping::Percent, Escaping::Backslash] {
+                let (contents, read) = round_trip(&creds, escaping);
+                assert!(!contents.contains('\r'), "{:?}", contents);
+                assert_eq!(read, creds);
+                let trimmed = ReadOptions::new()
+                    .escaping(escaping)
+                    .trim_whitespace(true)
+                    .read(contents.as_bytes())
+                    .unwrap();
+                assert_eq!(trimmed, creds);
+            }
+        }
+    }
+
+    #[test]
+    fn whitespace_is_kept_unless_trimmed() {
+        let contents = " alice : hunter2 \r\n";
+        let kept = ReadOptions::new().read(contents.as_bytes()).unwrap();
+        assert_eq!(kept.get(" alice "), Some(" hunter2 "));
+        assert_eq!(kept.get("alice"), None);
+
+        let trimmed = ReadOptions::new()
+            .trim_whitespace(true)
+            .read(contents.as_bytes())
+            .unwrap();
+        assert_eq!(trimmed.get("alice"), Some("hunter2"));
+        assert_eq!(trimmed.len(), 1);
+
+        let escaped = ReadOptions::new()
+            .trim_whitespace(true)
+            .escaping(Escaping::Percent)
+            .read("alice: %20pw%20 \n".as_bytes())
+            .unwrap();
+        assert_eq!(escaped.get("alice"), Some(" pw "));
+    }
+
+    #[test]
+    fn invalid_utf8_reports_the_line_and_offset() {
+        let contents = b"alice:a\r\nbob:b\xff\xfec\ncarol:c\n";
+        let error = ReadOptions::new().read(&contents[..]).unwrap_err();
+        assert!(
+            matches!(
+                error,
+                CredsError::InvalidUtf8 {
+                    line_number: 2,
+                    offset: 14
+                }
+            ),
+            "{:?}",
+            error
+        );
+        assert_eq!(
+            error.to_string(),
+            "line 2 is not valid UTF-8, at byte offset 14"
+        );
+        assert!(matches!(
+            ReadOptions::new().lookup(&contents[..], "carol"),
+            Err(CredsError::InvalidUtf8 { line_number: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn detects_each_format() {
+        let detect = |contents: &str| FileFormat::detect(contents.as_bytes());
+        assert_eq!(detect(""), Some(FileFormat::Lines));
+        assert_eq!(
+            detect("# users\n\nalice:hunter2\n"),
+            Some(FileFormat::Lines)
+        );
+        assert_eq!(
+            detect("alice:$2y$05$abcdefghijklmnopqrstuv\nbob:hunter2\n"),
+            Some(FileFormat::Lines)
+        );
+        assert_eq!(
+            detect(" {\"alice\": \"hunter2\"}\n"),
+            Some(FileFormat::Json)
+        );
+        assert_eq!(detect("{}"), Some(FileFormat::Json));
+    }
+
+    #[test]
+    fn entries_starting_with_a_brace_are_lines() {
+        let detect = |contents: &str| FileFormat::detect(contents.as_bytes());
+        assert_eq!(detect("{admin}:hunter2\n"), Some(FileFormat::Lines));
+        assert_eq!(detect("{SHA}:x\nbob:{b\n"), Some(FileFormat::Lines));
+    }
+
+    #[test]
+    fn unrecognized_contents_have_no_format() {
+        let detect = |contents: &[u8]| FileFormat::detect(contents);
+        assert_eq!(detect(b"alice\n"), None);
+        assert_eq!(detect(b"# users\nno delimiter here\n"), None);
+        assert_eq!(detect(b"\xff:\xfe\n"), None);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn only_valid_json_objects_are_json() {
+        let detect = |contents: &str| FileFormat::detect(contents.as_bytes());
+        assert_eq!(detect("{\"alice\" \"a\"}"), None);
+        assert_eq!(detect("{\"alice\":a}"), Some(FileFormat::Lines));
+        assert_eq!(detect("{SHA}:x\nbob:b}"), Some(FileFormat::Lines));
+    }
+}
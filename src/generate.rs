@@ -0,0 +1,251 @@
+//! Random password generation.
+
+use password_hash::rand_core::{OsRng, RngCore};
+use zeroize::Zeroize;
+
+const LOWERCASE: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &[u8] = b"0123456789";
+const SYMBOLS: &[u8] = b"!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+const AMBIGUOUS: &[u8] = b"0O1lI";
+
+/// A preset set of characters for [`PasswordSpec::charset`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Charset {
+    /// ASCII letters and digits.
+    Alphanumeric,
+    /// ASCII letters, digits, and symbols.
+    #[default]
+    Ascii,
+    /// ASCII letters and digits, except those that are easily confused with
+    /// each other when read: `0`, `O`, `1`, `l`, and `I`.
+    NoAmbiguous,
+}
+
+/// What kind of password [`generate_password`] creates.
+///
+/// The default is 20 characters drawn from ASCII letters, digits, and
+/// symbols. Passwords never contain whitespace, so they can always be written
+/// to a credentials file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordSpec {
+    length: usize,
+    lowercase: bool,
+    uppercase: bool,
+    digits: bool,
+    symbols: bool,
+    exclude_ambiguous: bool,
+}
+
+impl PasswordSpec {
+    /// Create the default spec.
+    pub fn new() -> Self {
+        Self {
+            length: 20,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            exclude_ambiguous: false,
+        }
+    }
+
+    /// Set which characters the password may contain from a preset.
+    ///
+    /// This replaces the settings of [`lowercase`](PasswordSpec::lowercase),
+    /// [`uppercase`](PasswordSpec::uppercase), [`digits`](PasswordSpec::digits),
+    /// [`symbols`](PasswordSpec::symbols), and
+    /// [`exclude_ambiguous`](PasswordSpec::exclude_ambiguous).
+    pub fn charset(self, charset: Charset) -> Self {
+        let (symbols, exclude_ambiguous) = match charset {
+            Charset::Alphanumeric => (false, false),
+            Charset::Ascii => (true, false),
+            Charset::NoAmbiguous => (false, true),
+        };
+        self.lowercase(true)
+            .uppercase(true)
+            .digits(true)
+            .symbols(symbols)
+            .exclude_ambiguous(exclude_ambiguous)
+    }
+
+    /// Sets the number of characters in the password.
+    pub fn length(mut self, length: usize) -> Self {
+        self.length = length;
+        self
+    }
+
+    /// Sets whether the password may contain lowercase ASCII letters.
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.lowercase = lowercase;
+        self
+    }
+
+    /// Sets whether the password may contain uppercase ASCII letters.
+    pub fn uppercase(mut self, uppercase: bool) -> Self {
+        self.uppercase = uppercase;
+        self
+    }
+
+    /// Sets whether the password may contain ASCII digits.
+    pub fn digits(mut self, digits: bool) -> Self {
+        self.digits = digits;
+        self
+    }
+
+    /// Sets whether the password may contain ASCII punctuation, like `!` and
+    /// `%`.
+    pub fn symbols(mut self, symbols: bool) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Sets whether to leave out characters that are easily confused with
+    /// each other when read: `0`, `O`, `1`, `l`, and `I`.
+    pub fn exclude_ambiguous(mut self, exclude_ambiguous: bool) -> Self {
+        self.exclude_ambiguous = exclude_ambiguous;
+        self
+    }
+
+    /// Returns the enabled character classes.
+    fn classes(&self) -> Vec<Vec<u8>> {
+        [
+            (self.lowercase, LOWERCASE),
+            (self.uppercase, UPPERCASE),
+            (self.digits, DIGITS),
+            (self.symbols, SYMBOLS),
+        ]
+        .into_iter()
+        .filter(|&(enabled, _)| enabled)
+        .map(|(_, class)| {
+            class
+                .iter()
+                .copied()
+                .filter(|byte| !(self.exclude_ambiguous && AMBIGUOUS.contains(byte)))
+                .collect()
+        })
+        .collect()
+    }
+}
+
+impl Default for PasswordSpec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Generates a random password following the given spec.
+///
+/// Characters are drawn uniformly from the enabled classes, using the
+/// operating system's secure random number generator. If the password is at
+/// least as long as the number of enabled classes, it contains at least one
+/// character of each class.
+///
+/// # Panics
+///
+/// Panics if the spec does not enable any character class.
+pub fn generate_password(spec: &PasswordSpec) -> String {
+    let classes = spec.classes();
+    assert!(
+        !classes.is_empty(),
+        "password spec must enable at least one character class"
+    );
+    let alphabet: Vec<u8> = classes.concat();
+    let require_all = spec.length >= classes.len();
+    loop {
+        let mut password: String = (0..spec.length)
+            .map(|_| char::from(alphabet[random_index(alphabet.len())]))
+            .collect();
+        // Retrying keeps the choice uniform among the passwords that satisfy
+        // the requirement, and usually succeeds within a few attempts.
+        if !require_all
+            || classes
+                .iter()
+                .all(|class| password.bytes().any(|byte| class.contains(&byte)))
+        {
+            return password;
+        }
+        password.zeroize();
+    }
+}
+
+/// Returns a uniformly random index below `len`, without modulo bias.
+pub(crate) fn random_index(len: usize) -> usize {
+    let len = len as u32;
+    let zone = u32::MAX - u32::MAX % len;
+    loop {
+        let value = OsRng.next_u32();
+        if value < zone {
+            return (value % len) as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::creds::Creds;
+
+    #[test]
+    fn default_spec_uses_every_class() {
+        let password = generate_password(&PasswordSpec::new());
+        assert_eq!(password.len(), 20);
+        for class in [LOWERCASE, UPPERCASE, DIGITS, SYMBOLS] {
+            assert!(password.bytes().any(|byte| class.contains(&byte)));
+        }
+    }
+
+    #[test]
+    fn respects_length_and_classes() {
+        let spec = PasswordSpec::new()
+            .length(64)
+            .uppercase(false)
+            .symbols(false);
+        for _ in 0..16 {
+            let password = generate_password(&spec);
+            assert_eq!(password.len(), 64);
+            assert!(password
+                .bytes()
+                .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit()));
+        }
+    }
+
+    #[test]
+    fn short_passwords_are_allowed() {
+        assert_eq!(generate_password(&PasswordSpec::new().length(0)), "");
+        assert_eq!(generate_password(&PasswordSpec::new().length(2)).len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one character class")]
+    fn panics_without_any_class() {
+        let spec = PasswordSpec::new()
+            .lowercase(false)
+            .uppercase(false)
+            .digits(false)
+            .symbols(false);
+        generate_password(&spec);
+    }
+
+    #[test]
+    fn insert_generated_stores_the_password() {
+        let mut creds = Creds::new();
+        let spec = PasswordSpec::new().symbols(false).length(12);
+        let password = creds.insert_generated("alice", &spec);
+        assert_eq!(password.len(), 12);
+        assert!(password.bytes().all(|byte| byte.is_ascii_alphanumeric()));
+        assert!(creds.verify("alice", &password));
+    }
+
+    #[test]
+    fn no_ambiguous_charset_leaves_out_ambiguous_characters() {
+        let spec = PasswordSpec::new()
+            .charset(Charset::NoAmbiguous)
+            .length(256);
+        let password = generate_password(&spec);
+        assert_eq!(password.len(), 256);
+        assert!(password
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() && !AMBIGUOUS.contains(&byte)));
+    }
+}
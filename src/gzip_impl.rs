@@ -0,0 +1,108 @@
+//! Gzip-compressed credential files, enabled by the `flate2` feature.
+//!
+//! The compressed data is the regular line format, so `gunzip` turns such a
+//! file back into a plain credentials file.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::Path,
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+
+use crate::{
+    creds::{Creds, CredsError},
+    file,
+    format::WriteOptions,
+};
+
+impl Creds {
+    /// Writes the credentials into the given writer, compressed with gzip.
+    ///
+    /// The credentials must be representable in the plain format; see
+    /// [`Creds::write`].
+    pub fn write_gz(&self, writer: impl Write) -> Result<(), CredsError> {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        WriteOptions::new().write(self, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// Parses gzip-compressed credentials from the given reader.
+    ///
+    /// Invalid gzip data is reported as a [`CredsError::ReadLine`] error. See
+    /// also: [`Creds::read`]
+    pub fn read_gz(reader: impl Read) -> Result<Self, CredsError> {
+        Self::read(BufReader::new(MultiGzDecoder::new(reader)))
+    }
+
+    /// Writes the credential store to the given file, compressed with gzip.
+    ///
+    /// The file is replaced atomically and gets the same permissions as with
+    /// [`Creds::write_to_file`]. The path is used as given; a `.gz` extension
+    /// is conventional but not required.
+    pub fn write_gz_to_file(&self, path: impl AsRef<Path>) -> Result<(), CredsError> {
+        file::write_atomic(path.as_ref(), |writer| self.write_gz(writer))
+    }
+
+    /// Parses a gzip-compressed credential store from the given file.
+    ///
+    /// See also: [`Creds::read_gz`]
+    pub fn read_gz_from_file(path: impl AsRef<Path>) -> Result<Self, CredsError> {
+        Self::read_gz(File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::file::TestDir;
+
+    fn creds() -> Creds {
+        (0..100)
+            .map(|index| (format!("user{}", index), format!("password{}", index)))
+            .collect()
+    }
+
+    #[test]
+    fn compressed_data_round_trips() {
+        let creds = creds();
+        let mut compressed = Vec::new();
+        creds.write_gz(&mut compressed).unwrap();
+        assert_eq!(&compressed[..2], [0x1f, 0x8b]);
+
+        let mut plain = String::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, creds.to_string_contents().unwrap());
+        assert_eq!(Creds::read_gz(&compressed[..]).unwrap(), creds);
+    }
+
+    #[test]
+    fn compressed_files_reload_identically() {
+        let dir = TestDir::new();
+        let path = dir.join("creds.gz");
+        let creds = creds();
+        creds.write_gz_to_file(&path).unwrap();
+        assert_eq!(dir.file_names(), ["creds.gz"]);
+
+        let read = Creds::read_gz_from_file(&path).unwrap();
+        assert_eq!(read, creds);
+        assert_eq!(
+            read.to_string_contents().unwrap(),
+            creds.to_string_contents().unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_gzip_data_is_an_error() {
+        assert!(matches!(
+            Creds::read_gz(&b"alice:secret\n"[..]),
+            Err(CredsError::ReadLine { .. })
+        ));
+    }
+}
@@ -0,0 +1,480 @@
+//! Argon2id password hashing.
+//!
+//! Hashed passwords are stored as [PHC strings] like
+//! `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`, which fit into the
+//! password field of the line-based credential format unchanged.
+//!
+//! Use [`Creds::insert_hashed`] to mix hashed entries into a regular store,
+//! or [`HashedCreds`] for a store that only ever holds hashes.
+//!
+//! [PHC strings]: https://github.com/P-H-C/phc-string-format/blob/master/phc-sf-spec.md
+
+use std::{
+    fmt,
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+
+use crate::{
+    creds::{Creds, CredsError, Usernames},
+    hasher,
+};
+
+/// The prefix shared by all Argon2 PHC strings.
+const ARGON2_PREFIX: &str = "$argon2";
+
+/// The largest memory cost that [`verify_hash`] accepts, 1 GiB.
+pub const MAX_MEMORY_KIB: u32 = 1 << 20;
+
+/// The largest iteration count that [`verify_hash`] accepts.
+pub const MAX_ITERATIONS: u32 = 64;
+
+/// The largest parallelism that [`verify_hash`] accepts.
+pub const MAX_PARALLELISM: u32 = 64;
+
+/// Cost parameters for Argon2id hashing.
+///
+/// The defaults follow the recommendations of the [`argon2`] crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashParams {
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl HashParams {
+    /// The default parameters, usable in constants.
+    pub(crate) const DEFAULT: Self = Self {
+        memory_kib: Params::DEFAULT_M_COST,
+        iterations: Params::DEFAULT_T_COST,
+        parallelism: Params::DEFAULT_P_COST,
+    };
+
+    /// Create a new set of hash parameters.
+    ///
+    /// - `memory_kib` is the memory cost, in KiB.
+    /// - `iterations` is the number of passes over the memory.
+    /// - `parallelism` is the number of lanes.
+    ///
+    /// Returns an error if Argon2 does not accept the given combination of
+    /// parameters, for example if `iterations` is zero. Note that hashes made
+    /// with costs above [`MAX_MEMORY_KIB`], [`MAX_ITERATIONS`], or
+    /// [`MAX_PARALLELISM`] will not [verify](verify_hash).
+    pub fn new(
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    ) -> Result<Self, InvalidHashParams> {
+        Params::new(memory_kib, iterations, parallelism, None).map_err(|_| InvalidHashParams)?;
+        Ok(Self {
+            memory_kib,
+            iterations,
+            parallelism,
+        })
+    }
+
+    /// The memory cost, in KiB.
+    pub fn memory_kib(&self) -> u32 {
+        self.memory_kib
+    }
+
+    /// The number of passes over the memory.
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// The number of lanes.
+    pub fn parallelism(&self) -> u32 {
+        self.parallelism
+    }
+
+    /// Returns `true` if none of the costs exceed [`MAX_MEMORY_KIB`],
+    /// [`MAX_ITERATIONS`], and [`MAX_PARALLELISM`].
+    pub(crate) fn within_limits(&self) -> bool {
+        self.memory_kib <= MAX_MEMORY_KIB
+            && self.iterations <= MAX_ITERATIONS
+            && self.parallelism <= MAX_PARALLELISM
+    }
+
+    pub(crate) fn argon2(&self) -> Argon2<'static> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .expect("hash parameters are validated on construction");
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+    }
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The error returned by [`HashParams::new`] for parameters that Argon2 does
+/// not accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidHashParams;
+
+impl fmt::Display for InvalidHashParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid argon2 hash parameters")
+    }
+}
+
+impl std::error::Error for InvalidHashParams {}
+
+/// Hash the given password with Argon2id and a random salt, returning the
+/// PHC string.
+pub fn hash_password(password: &str, params: &HashParams) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    params
+        .argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("argon2 hashing with validated parameters cannot fail")
+        .to_string()
+}
+
+/// Returns `true` if the given stored password looks like an Argon2 PHC
+/// string.
+pub fn is_hashed(stored: &str) -> bool {
+    stored.starts_with(ARGON2_PREFIX)
+}
+
+/// Checks the given password against an Argon2 PHC string.
+///
+/// The cost parameters are read from the PHC string itself. Returns `false`
+/// if the password does not match or if the PHC string is malformed.
+///
+/// Since a crafted hash could otherwise make verifying take unbounded memory
+/// or time, hashes with costs above [`MAX_MEMORY_KIB`], [`MAX_ITERATIONS`],
+/// or [`MAX_PARALLELISM`] are rejected without computing anything.
+pub fn verify_hash(password: &str, hash: &str) -> bool {
+    let hash = match PasswordHash::new(hash) {
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+    let within_limits = Params::try_from(&hash).is_ok_and(|params| {
+        params.m_cost() <= MAX_MEMORY_KIB
+            && params.t_cost() <= MAX_ITERATIONS
+            && params.p_cost() <= MAX_PARALLELISM
+    });
+    within_limits
+        && Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+}
+
+/// A credential store that only stores Argon2id hashes of passwords.
+///
+/// This wraps a [`Creds`] store, hashing every password as it is inserted, so
+/// that plaintext passwords are never kept in memory or written to disk. The
+/// on-disk format is the same line-based format, with a PHC string in place
+/// of each password:
+///
+/// ```text
+/// <username1>:$argon2id$v=19$...
+/// <username2>:$argon2id$v=19$...
+/// ```
+///
+/// Use a plain [`Creds`] store if plaintext storage is really what you want.
+#[derive(Clone, Default)]
+pub struct HashedCreds {
+    creds: Creds,
+}
+
+impl HashedCreds {
+    /// Create a new empty store using the default hash parameters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new empty store using the given hash parameters.
+    pub fn with_params(params: HashParams) -> Self {
+        let mut hashed = Self::new();
+        hashed.set_params(params);
+        hashed
+    }
+
+    /// Create a store holding hashes of every password in the given plaintext
+    /// store, using the given hash parameters.
+    ///
+    /// Passwords in `creds` that are already hashed with Argon2, bcrypt, or
+    /// scrypt are copied as they are, and every credential keeps its position
+    /// and metadata. The original store is not changed.
+    ///
+    /// Like [`Creds::upgrade_plaintext_to_hashed`], this returns
+    /// [`CredsError::WeakHash`] if `creds` holds `$apr1$` or `{SHA}` hashes,
+    /// which are too weak to keep and cannot be hashed again.
+    ///
+    /// During a transition, a [`Creds`] store may hold both plaintext
+    /// passwords and hashes, since [`Creds::verify`] checks each entry in
+    /// whichever format it is stored. Users can then be moved over
+    /// gradually, for example by calling [`Creds::insert_hashed`] after they
+    /// log in successfully, or all at once with
+    /// [`Creds::upgrade_plaintext_to_hashed`] or this function, followed by
+    /// writing the store back to its file.
+    pub fn from_plaintext(creds: &Creds, params: HashParams) -> Result<Self, CredsError> {
+        let mut creds = creds.clone();
+        creds.set_hash_params(params);
+        creds.upgrade_plaintext_to_hashed(&params)?;
+        Ok(Self::from(creds))
+    }
+
+    /// Returns the parameters used to hash newly inserted passwords.
+    pub fn params(&self) -> &HashParams {
+        self.creds.hash_params()
+    }
+
+    /// Sets the parameters used to hash newly inserted passwords.
+    ///
+    /// Existing hashes keep the parameters they were created with, and still
+    /// verify.
+    pub fn set_params(&mut self, params: HashParams) {
+        self.creds.set_hash_params(params);
+    }
+
+    /// Returns the number of credentials in the store.
+    pub fn len(&self) -> usize {
+        self.creds.len()
+    }
+
+    /// Returns `true` if the store contains no credentials.
+    pub fn is_empty(&self) -> bool {
+        self.creds.is_empty()
+    }
+
+    /// Remove all stored credentials.
+    pub fn clear(&mut self) {
+        self.creds.clear();
+    }
+
+    /// Hash the given password and store it for the given username.
+    ///
+    /// If a hash already exists for the given username, it will be overwritten.
+    pub fn insert(&mut self, username: impl Into<String>, password: &str) {
+        self.creds.insert_hashed(username, password);
+    }
+
+    /// Retrieve the stored PHC string for the given username.
+    pub fn get_hash(&self, username: &str) -> Option<&str> {
+        self.creds.get(username)
+    }
+
+    /// Checks whether the given password matches the hash stored for the
+    /// given username.
+    ///
+    /// A missing username is checked against a dummy hash with the store's
+    /// parameters, so that it takes about as long as a wrong password.
+    ///
+    /// Entries that are not recognized hashes, such as plaintext passwords in
+    /// a file read with [`HashedCreds::read`], never verify, and are treated
+    /// like missing usernames.
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        let hashed = self.creds.get(username).is_some_and(hasher::is_recognized);
+        if hashed {
+            self.creds.verify(username, password)
+        } else {
+            self.creds.reject_with_dummy_hash(password)
+        }
+    }
+
+    /// Remove the credential for the given username, returning its hash.
+    ///
+    /// Like [`Creds::remove`], this preserves the insertion order of the
+    /// remaining entries.
+    pub fn remove(&mut self, username: &str) -> Option<String> {
+        self.creds.remove(username)
+    }
+
+    /// Returns an iterator over the stored usernames, in insertion order.
+    pub fn usernames(&self) -> Usernames<'_> {
+        self.creds.usernames()
+    }
+
+    /// Returns the underlying store of usernames and PHC strings.
+    pub fn as_creds(&self) -> &Creds {
+        &self.creds
+    }
+
+    /// Converts this into the underlying store of usernames and PHC strings.
+    pub fn into_creds(self) -> Creds {
+        self.creds
+    }
+
+    /// Writes the usernames and hashes into the given writer.
+    ///
+    /// See also: [`Creds::write`]
+    pub fn write(&self, writer: impl Write) -> Result<(), CredsError> {
+        self.creds.write(writer)
+    }
+
+    /// Parses a hashed credentials file from the given reader.
+    ///
+    /// See also: [`Creds::read`]
+    pub fn read(reader: impl BufRead) -> Result<Self, CredsError> {
+        Creds::read(reader).map(Self::from)
+    }
+
+    /// Writes the store to the given file.
+    ///
+    /// See also: [`Creds::write_to_file`]
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> Result<(), CredsError> {
+        self.creds.write_to_file(path)
+    }
+
+    /// Parses a hashed credential store from the given file.
+    ///
+    /// See also: [`Creds::read_from_file`]
+    pub fn read_from_file(path: impl AsRef<Path>) -> Result<Self, CredsError> {
+        Creds::read_from_file(path).map(Self::from)
+    }
+}
+
+impl fmt::Debug for HashedCreds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HashedCreds")
+            .field("creds", &self.creds)
+            .finish()
+    }
+}
+
+impl From<Creds> for HashedCreds {
+    /// Wraps a store that already holds PHC strings, such as one read from a
+    /// hashed credentials file.
+    ///
+    /// The passwords are taken as they are, without hashing them. Entries
+    /// that are not recognized hashes never [verify](HashedCreds::verify);
+    /// use [`HashedCreds::from_plaintext`] to hash them instead.
+    fn from(creds: Creds) -> Self {
+        Self { creds }
+    }
+}
+
+impl From<HashedCreds> for Creds {
+    fn from(hashed: HashedCreds) -> Self {
+        hashed.into_creds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::creds::Creds;
+
+    fn cheap_params() -> HashParams {
+        HashParams::new(64, 1, 1).unwrap()
+    }
+
+    #[test]
+    fn hashed_entries_round_trip() {
+        let mut creds = Creds::new();
+        creds.set_hash_params(cheap_params());
+        creds.insert_hashed("alice", "secret");
+        creds.insert("bob", "plain");
+        let stored = creds.get("alice").unwrap();
+        assert!(stored.starts_with("$argon2id$v=19$m=64,t=1,p=1$"));
+        assert!(!stored.contains("secret"));
+
+        let mut contents = Vec::new();
+        creds.write(&mut contents).unwrap();
+        let read = Creds::read(&contents[..]).unwrap();
+        assert!(read.verify("alice", "secret"));
+        assert!(!read.verify("alice", "wrong"));
+        assert!(!read.verify("alice", stored));
+        assert!(read.verify("bob", "plain"));
+    }
+
+    #[test]
+    fn salts_are_random() {
+        let params = cheap_params();
+        assert_ne!(
+            hash_password("secret", &params),
+            hash_password("secret", &params)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        assert_eq!(HashParams::new(64, 0, 1), Err(InvalidHashParams));
+        assert_eq!(HashParams::new(64, 1, 0), Err(InvalidHashParams));
+    }
+
+    #[test]
+    fn hashed_creds_rejects_missing_users() {
+        let mut hashed = HashedCreds::with_params(cheap_params());
+        hashed.insert("alice", "secret");
+        assert!(hashed.verify("alice", "secret"));
+        assert!(!hashed.verify("alice", "wrong"));
+        assert!(!hashed.verify("bob", "secret"));
+    }
+
+    #[test]
+    fn from_plaintext_verifies_the_same_passwords() {
+        let mut plain = Creds::new();
+        plain.set_hash_params(cheap_params());
+        plain.insert("alice", "secret");
+        plain.insert("bob", "hunter2");
+        plain.insert_hashed("carol", "already");
+
+        let hashed = HashedCreds::from_plaintext(&plain, cheap_params()).unwrap();
+        assert_eq!(hashed.params(), &cheap_params());
+        assert_eq!(hashed.len(), 3);
+        for (username, password) in [
+            ("alice", "secret"),
+            ("bob", "hunter2"),
+            ("carol", "already"),
+        ] {
+            assert!(plain.verify(username, password));
+            assert!(hashed.verify(username, password), "{}", username);
+            assert!(!hashed.verify(username, "wrong"));
+            assert!(is_hashed(hashed.get_hash(username).unwrap()));
+        }
+        assert_eq!(hashed.get_hash("carol"), plain.get("carol"));
+        assert_eq!(plain.get("alice"), Some("secret"));
+    }
+
+    #[test]
+    fn hashed_creds_never_verify_plaintext_entries() {
+        let hash = hash_password("secret", &cheap_params());
+        let contents = format!("alice:{}\nbob:hunter2\n", hash);
+
+        let read = HashedCreds::read(contents.as_bytes()).unwrap();
+        let wrapped = HashedCreds::from(Creds::read(contents.as_bytes()).unwrap());
+        for hashed in [read, wrapped] {
+            assert!(hashed.verify("alice", "secret"));
+            assert!(!hashed.verify("bob", "hunter2"));
+            assert!(!hashed.verify("bob", ""));
+        }
+    }
+
+    #[test]
+    fn verify_hash_rejects_costs_above_the_limits() {
+        let params = cheap_params();
+        let hash = hash_password("secret", &params);
+        assert!(verify_hash("secret", &hash));
+        for costs in [
+            format!("m={},t=1,p=1", MAX_MEMORY_KIB + 1),
+            format!("m=64,t={},p=1", MAX_ITERATIONS + 1),
+            format!("m=64,t=1,p={}", MAX_PARALLELISM + 1),
+        ] {
+            let crafted = hash.replace("m=64,t=1,p=1", &costs);
+            assert_ne!(crafted, hash);
+            assert!(!verify_hash("secret", &crafted), "{}", crafted);
+        }
+    }
+
+    #[test]
+    fn from_plaintext_refuses_weak_hashes() {
+        let mut plain = Creds::new();
+        plain.insert("alice", "secret");
+        plain.insert("bob", crate::htpasswd::hash_apr1("hunter2"));
+        assert!(matches!(
+            HashedCreds::from_plaintext(&plain, cheap_params()),
+            Err(CredsError::WeakHash { username }) if username == "bob"
+        ));
+    }
+}
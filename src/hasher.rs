@@ -0,0 +1,454 @@
+//! Password hashing algorithms behind a common interface.
+//!
+//! Each supported algorithm implements [`PasswordHasher`], and stored hashes
+//! are recognized by their prefix, so a single [`Creds`] store can hold hashes
+//! of different kinds. [`Creds::verify`] checks each entry with the algorithm
+//! it was hashed with, through [`verify`].
+
+use std::{error::Error, fmt, sync::Arc};
+
+use password_hash::{
+    rand_core::OsRng, PasswordHash, PasswordHasher as _, PasswordVerifier as _, SaltString,
+};
+
+use crate::{
+    creds::Creds,
+    hash::{self, HashParams},
+    htpasswd::{self, InvalidBcryptCost},
+};
+
+/// A password hashing algorithm.
+pub trait PasswordHasher {
+    /// Hashes the given password with a new random salt.
+    fn hash(&self, password: &str) -> String;
+
+    /// Checks the given password against a stored hash.
+    ///
+    /// Returns `false` if the password does not match, or if the hash is
+    /// malformed or was not produced by this algorithm.
+    fn verify(&self, password: &str, stored: &str) -> bool;
+
+    /// Returns `true` if the stored hash has this algorithm's prefix.
+    fn recognizes(&self, stored: &str) -> bool;
+}
+
+/// A hasher that can be kept in a [`Creds`] store, which may be shared
+/// between threads.
+pub(crate) type SharedHasher = Arc<dyn PasswordHasher + Send + Sync>;
+
+/// Argon2id, with hashes like `$argon2id$v=19$...`.
+///
+/// See the [`hash`] module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Argon2Hasher {
+    params: HashParams,
+}
+
+impl Argon2Hasher {
+    /// Creates an Argon2id hasher with the given cost parameters.
+    ///
+    /// Hashes record their parameters, so any hash can be verified
+    /// regardless.
+    pub fn new(params: HashParams) -> Self {
+        Self { params }
+    }
+}
+
+impl PasswordHasher for Argon2Hasher {
+    fn hash(&self, password: &str) -> String {
+        hash::hash_password(password, &self.params)
+    }
+
+    fn verify(&self, password: &str, stored: &str) -> bool {
+        self.recognizes(stored) && hash::verify_hash(password, stored)
+    }
+
+    fn recognizes(&self, stored: &str) -> bool {
+        hash::is_hashed(stored)
+    }
+}
+
+/// bcrypt, with hashes like `$2y$10$...`.
+///
+/// Hashes with the `$2b$`, `$2a$`, and `$2x$` prefixes are recognized too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BcryptHasher {
+    cost: u32,
+}
+
+impl BcryptHasher {
+    /// Creates a bcrypt hasher with the given cost.
+    ///
+    /// Returns an error if the cost is outside of the range bcrypt accepts, 4
+    /// to 31.
+    pub fn new(cost: u32) -> Result<Self, InvalidBcryptCost> {
+        let cost = htpasswd::check_bcrypt_cost(cost)?;
+        Ok(Self { cost })
+    }
+}
+
+impl Default for BcryptHasher {
+    /// Uses [`DEFAULT_BCRYPT_COST`](htpasswd::DEFAULT_BCRYPT_COST).
+    fn default() -> Self {
+        Self {
+            cost: htpasswd::DEFAULT_BCRYPT_COST,
+        }
+    }
+}
+
+impl PasswordHasher for BcryptHasher {
+    fn hash(&self, password: &str) -> String {
+        htpasswd::hash_bcrypt(password, self.cost)
+            .expect("the cost was checked when creating the hasher")
+    }
+
+    fn verify(&self, password: &str, stored: &str) -> bool {
+        self.recognizes(stored) && htpasswd::verify_htpasswd_hash(password, stored) == Some(true)
+    }
+
+    fn recognizes(&self, stored: &str) -> bool {
+        htpasswd::BCRYPT_PREFIXES
+            .iter()
+            .any(|prefix| stored.starts_with(prefix))
+    }
+}
+
+/// Apache's MD5-crypt variant, with hashes like `$apr1$<salt>$...`.
+///
+/// This is weaker than bcrypt; see [`htpasswd::hash_apr1`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Apr1Hasher;
+
+impl PasswordHasher for Apr1Hasher {
+    fn hash(&self, password: &str) -> String {
+        htpasswd::hash_apr1(password)
+    }
+
+    fn verify(&self, password: &str, stored: &str) -> bool {
+        self.recognizes(stored) && htpasswd::verify_htpasswd_hash(password, stored) == Some(true)
+    }
+
+    fn recognizes(&self, stored: &str) -> bool {
+        stored.starts_with(htpasswd::APR1_PREFIX)
+    }
+}
+
+const SCRYPT_PREFIX: &str = "$scrypt$";
+
+/// The largest amount of memory an scrypt hash may take to compute, in bytes.
+///
+/// Hashes record their own costs, so this stops a crafted hash from taking
+/// all of the memory to verify.
+const MAX_SCRYPT_MEMORY: u64 = 1 << 30;
+
+/// The largest parallelization parameter an scrypt hash may have.
+const MAX_SCRYPT_PARALLELISM: u32 = 16;
+
+/// scrypt, with hashes like `$scrypt$ln=17,r=8,p=1$<salt>$<hash>`.
+///
+/// Hashes are computed by the RustCrypto `scrypt` crate, in the PHC string
+/// format it shares with the Python `passlib` library. Verifying rejects hashes that would take more than 1
+/// GiB of memory or have a parallelization parameter above 16, since the
+/// costs are read from the hash itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScryptHasher {
+    log_n: u8,
+    r: u32,
+    p: u32,
+}
+
+impl ScryptHasher {
+    /// Creates an scrypt hasher with the CPU and memory cost `N = 2^log_n`,
+    /// the block size `r`, and the parallelization parameter `p`.
+    ///
+    /// Returns an error if scrypt does not accept the combination, or if
+    /// hashes made with it could not be verified because of the limits
+    /// above.
+    pub fn new(log_n: u8, r: u32, p: u32) -> Result<Self, InvalidScryptParams> {
+        match scrypt_params(log_n, r, p) {
+            Some(_) => Ok(Self { log_n, r, p }),
+            None => Err(InvalidScryptParams),
+        }
+    }
+}
+
+/// Returns the scrypt parameters with the given costs, if scrypt accepts
+/// them and they are within the limits for verifying.
+fn scrypt_params(log_n: u8, r: u32, p: u32) -> Option<scrypt::Params> {
+    let within_limits = (1..64).contains(&log_n)
+        && (1..=MAX_SCRYPT_PARALLELISM).contains(&p)
+        && 128u64
+            .checked_mul(u64::from(r))
+            .and_then(|block| block.checked_mul(1 << log_n))
+            .is_some_and(|memory| memory <= MAX_SCRYPT_MEMORY);
+    if !within_limits {
+        return None;
+    }
+    scrypt::Params::new(log_n, r, p, scrypt::Params::RECOMMENDED_LEN).ok()
+}
+
+/// Returns the costs of an scrypt PHC string, if it has valid ones.
+fn scrypt_costs(stored: &str) -> Option<(u8, u32, u32)> {
+    let hash = PasswordHash::new(stored)
+        .ok()
+        .filter(|hash| hash.algorithm == scrypt::ALG_ID)?;
+    let params = scrypt::Params::try_from(&hash).ok()?;
+    let costs = (params.log_n(), params.r(), params.p());
+    scrypt_params(costs.0, costs.1, costs.2).map(|_| costs)
+}
+
+impl Default for ScryptHasher {
+    /// Uses `log_n = 17`, `r = 8`, and `p = 1`, which take 128 MiB of memory.
+    fn default() -> Self {
+        Self {
+            log_n: 17,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+impl PasswordHasher for ScryptHasher {
+    fn hash(&self, password: &str) -> String {
+        let params = scrypt_params(self.log_n, self.r, self.p)
+            .expect("the costs are checked when creating the hasher");
+        let salt = SaltString::generate(&mut OsRng);
+        scrypt::Scrypt
+            .hash_password_customized(password.as_bytes(), None, None, params, &salt)
+            .expect("hashing with valid parameters cannot fail")
+            .to_string()
+    }
+
+    fn verify(&self, password: &str, stored: &str) -> bool {
+        // Check the costs before verifying, which computes the hash with them.
+        self.recognizes(stored)
+            && scrypt_costs(stored).is_some()
+            && PasswordHash::new(stored).is_ok_and(|hash| {
+                scrypt::Scrypt
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+    }
+
+    fn recognizes(&self, stored: &str) -> bool {
+        stored.starts_with(SCRYPT_PREFIX)
+    }
+}
+
+/// The error returned by [`ScryptHasher::new`] for parameters that are not
+/// accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidScryptParams;
+
+impl fmt::Display for InvalidScryptParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid scrypt hash parameters")
+    }
+}
+
+impl Error for InvalidScryptParams {}
+
+/// Returns the hasher for the algorithm that produced the given hash, or
+/// `None` if it is not a recognized hash.
+///
+/// The returned hasher uses default costs, which do not matter for
+/// verifying.
+pub fn hasher_for(stored: &str) -> Option<&'static dyn PasswordHasher> {
+    static ARGON2: Argon2Hasher = Argon2Hasher {
+        params: HashParams::DEFAULT,
+    };
+    static BCRYPT: BcryptHasher = BcryptHasher {
+        cost: htpasswd::DEFAULT_BCRYPT_COST,
+    };
+    static SCRYPT: ScryptHasher = ScryptHasher {
+        log_n: 17,
+        r: 8,
+        p: 1,
+    };
+    let hashers: [&'static dyn PasswordHasher; 4] = [&ARGON2, &BCRYPT, &Apr1Hasher, &SCRYPT];
+    hashers.into_iter().find(|hasher| hasher.recognizes(stored))
+}
+
+/// Checks the given password against a stored hash of any recognized kind.
+///
+/// Returns `None` if the stored password is not a recognized hash, in which
+/// case it is presumably plaintext. Besides the hashes of [`hasher_for`],
+/// this recognizes the `{SHA}` hashes of old `.htpasswd` files, which have no
+/// hasher since new ones should not be created.
+pub fn verify(password: &str, stored: &str) -> Option<bool> {
+    match hasher_for(stored) {
+        Some(hasher) => Some(hasher.verify(password, stored)),
+        None => htpasswd::verify_htpasswd_hash(password, stored),
+    }
+}
+
+/// Returns `true` if [`verify`] recognizes the scheme of the stored hash.
+pub(crate) fn is_recognized(stored: &str) -> bool {
+    hasher_for(stored).is_some() || htpasswd::is_htpasswd_hash(stored)
+}
+
+/// Hashes a new password with the scheme of the stored hash it replaces, or
+/// returns `None` if the stored password is not a recognized hash.
+///
+/// Argon2 uses the given parameters, and bcrypt and scrypt keep the costs of
+/// the stored hash. `{SHA}` hashes are replaced with bcrypt, since they are too weak to
+/// create anew.
+pub(crate) fn rehash(password: &str, stored: &str, params: &HashParams) -> Option<String> {
+    if hash::is_hashed(stored) {
+        return Some(Argon2Hasher::new(*params).hash(password));
+    }
+    if BcryptHasher::default().recognizes(stored) {
+        let cost = stored
+            .get(4..6)
+            .and_then(|cost| cost.parse().ok())
+            .filter(|cost| (4..=31).contains(cost))
+            .unwrap_or(htpasswd::DEFAULT_BCRYPT_COST);
+        return Some(BcryptHasher { cost }.hash(password));
+    }
+    if let Some((log_n, r, p)) = scrypt_costs(stored) {
+        return Some(ScryptHasher { log_n, r, p }.hash(password));
+    }
+    match hasher_for(stored) {
+        Some(hasher) => Some(hasher.hash(password)),
+        None if htpasswd::is_htpasswd_hash(stored) => Some(BcryptHasher::default().hash(password)),
+        None => None,
+    }
+}
+
+impl Creds {
+    /// Add a new username and password pair, storing a hash of the password
+    /// made by the given hasher.
+    ///
+    /// This lets a store hold hashes of different kinds, which
+    /// [`Creds::verify`] tells apart by their prefix. If a password already
+    /// exists for the given username, it will be overwritten.
+    ///
+    /// See also: [`Creds::insert_hashed`]
+    pub fn insert_hashed_with(
+        &mut self,
+        username: impl Into<String>,
+        password: &str,
+        hasher: &dyn PasswordHasher,
+    ) {
+        self.insert(username, hasher.hash(password));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A hasher of each kind, with cheap costs so that the tests run quickly.
+    fn hashers() -> [Box<dyn PasswordHasher>; 4] {
+        [
+            Box::new(Argon2Hasher::new(HashParams::new(64, 1, 1).unwrap())),
+            Box::new(BcryptHasher::new(4).unwrap()),
+            Box::new(Apr1Hasher),
+            Box::new(ScryptHasher::new(4, 8, 1).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn each_hasher_verifies_its_hashes() {
+        for hasher in hashers() {
+            let stored = hasher.hash("secret");
+            assert!(hasher.recognizes(&stored), "{}", stored);
+            assert!(hasher.verify("secret", &stored), "{}", stored);
+            assert!(!hasher.verify("wrong", &stored), "{}", stored);
+            assert_eq!(verify("secret", &stored), Some(true), "{}", stored);
+            assert_eq!(verify("wrong", &stored), Some(false), "{}", stored);
+        }
+    }
+
+    #[test]
+    fn hashers_only_recognize_their_own_hashes() {
+        let hashers = hashers();
+        for (index, hasher) in hashers.iter().enumerate() {
+            let stored = hasher.hash("secret");
+            for (other_index, other) in hashers.iter().enumerate() {
+                assert_eq!(
+                    other.recognizes(&stored),
+                    index == other_index,
+                    "{}",
+                    stored
+                );
+                if index != other_index {
+                    assert!(!other.verify("secret", &stored), "{}", stored);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn one_store_holds_mixed_hashes() {
+        let mut creds = Creds::new();
+        let usernames = ["argon2", "bcrypt", "apr1", "scrypt"];
+        for (username, hasher) in usernames.into_iter().zip(hashers()) {
+            creds.insert_hashed_with(username, username, &*hasher);
+        }
+        creds.insert("sha1", "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=");
+        creds.insert("plain", "password");
+        for username in usernames {
+            assert!(creds.verify(username, username), "{}", username);
+            assert!(!creds.verify(username, "wrong"), "{}", username);
+        }
+        assert!(creds.verify("sha1", "password"));
+        assert!(creds.verify("plain", "password"));
+        assert_eq!(verify("password", "password"), None);
+    }
+
+    #[test]
+    fn scrypt_hasher_rejects_invalid_params() {
+        assert_eq!(ScryptHasher::new(0, 8, 1), Err(InvalidScryptParams));
+        assert_eq!(ScryptHasher::new(4, 0, 1), Err(InvalidScryptParams));
+        assert_eq!(ScryptHasher::new(4, 8, 0), Err(InvalidScryptParams));
+        assert_eq!(ScryptHasher::new(30, 8, 1), Err(InvalidScryptParams));
+        assert!(ScryptHasher::new(17, 8, 1).is_ok());
+    }
+
+    #[test]
+    fn rehash_keeps_the_costs() {
+        let params = HashParams::new(64, 1, 1).unwrap();
+        let bcrypt = BcryptHasher::new(5).unwrap().hash("old");
+        let rehashed = rehash("new", &bcrypt, &params).unwrap();
+        assert!(rehashed.starts_with("$2y$05$"));
+        let scrypt = ScryptHasher::new(5, 4, 2).unwrap().hash("old");
+        let rehashed = rehash("new", &scrypt, &params).unwrap();
+        assert!(rehashed.starts_with("$scrypt$ln=5,r=4,p=2$"));
+        assert!(verify("new", &rehashed).unwrap());
+        assert_eq!(rehash("new", "plaintext", &params), None);
+    }
+
+    #[test]
+    fn bcrypt_hasher_rejects_invalid_costs() {
+        assert_eq!(BcryptHasher::new(3), Err(InvalidBcryptCost));
+        assert_eq!(BcryptHasher::new(32), Err(InvalidBcryptCost));
+        assert!(BcryptHasher::new(4).is_ok());
+        assert!(BcryptHasher::new(31).is_ok());
+    }
+
+    #[test]
+    fn scrypt_hasher_verifies_existing_hashes() {
+        let stored = "$scrypt$ln=4,r=8,p=1$gzFVqenkrCLjjA/Lhg1O9Q$\
+                      EvPzdr9Zq/tMRc+uwqhuaHXskj8zHcZVOTdiFDmxSZ8";
+        let hasher = ScryptHasher::default();
+        assert!(hasher.verify("hunter2", stored));
+        assert!(!hasher.verify("wrong", stored));
+        assert!(!hasher.verify("hunter2", "$scrypt$ln=4,r=8,p=1$c2FsdA$"));
+
+        let stored = ScryptHasher::new(4, 8, 1).unwrap().hash("secret");
+        assert!(stored.starts_with("$scrypt$ln=4,r=8,p=1$"), "{}", stored);
+        assert!(hasher.verify("secret", &stored));
+    }
+
+    #[test]
+    fn scrypt_hasher_rejects_hashes_with_huge_costs() {
+        let stored = ScryptHasher::new(4, 8, 1).unwrap().hash("secret");
+        for costs in ["ln=40,r=8,p=1", "ln=4,r=8,p=17"] {
+            let huge = stored.replace("ln=4,r=8,p=1", costs);
+            assert!(!ScryptHasher::default().verify("secret", &huge), "{}", huge);
+            assert_eq!(scrypt_costs(&huge), None);
+        }
+        assert_eq!(scrypt_costs(&stored), Some((4, 8, 1)));
+    }
+}
@@ -0,0 +1,284 @@
+//! Apache `.htpasswd` hash formats.
+//!
+//! An `.htpasswd` file uses the same `<username>:<hash>` line format as
+//! [`Creds`], so it can be read with [`Creds::read_htpasswd`]. The following
+//! hash schemes are recognized by [`Creds::verify`]:
+//!
+//! - bcrypt, with the `$2y$`, `$2b$`, `$2a$`, or `$2x$` prefix.
+//! - Apache's MD5-crypt variant, with the `$apr1$` prefix.
+//! - Unsalted SHA-1, with the `{SHA}` prefix. This is weak, and only
+//!   supported for reading existing files.
+//!
+//! [`Creds`]: crate::creds::Creds
+//! [`Creds::read_htpasswd`]: crate::creds::Creds::read_htpasswd
+//! [`Creds::verify`]: crate::creds::Creds::verify
+
+use std::{error::Error, fmt};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+use crate::{creds::constant_time_eq, generate};
+
+/// The bcrypt cost used by [`Creds::write_htpasswd`].
+///
+/// [`Creds`]: crate::creds::Creds
+/// [`Creds::write_htpasswd`]: crate::creds::Creds::write_htpasswd
+pub const DEFAULT_BCRYPT_COST: u32 = 10;
+
+pub(crate) const BCRYPT_PREFIXES: [&str; 4] = ["$2y$", "$2b$", "$2a$", "$2x$"];
+pub(crate) const APR1_PREFIX: &str = "$apr1$";
+const SHA1_PREFIX: &str = "{SHA}";
+
+/// The characters of salts and hashes in the crypt formats.
+const CRYPT_ALPHABET: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Returns `true` if the given stored password is an MD5-based `$apr1$` or
+/// unsalted `{SHA}` hash, which are too weak to keep when migrating a store.
+pub(crate) fn is_weak_hash(stored: &str) -> bool {
+    stored.starts_with(APR1_PREFIX) || stored.starts_with(SHA1_PREFIX)
+}
+
+/// Returns `true` if the given stored password uses one of the recognized
+/// `.htpasswd` hash schemes.
+pub fn is_htpasswd_hash(stored: &str) -> bool {
+    BCRYPT_PREFIXES
+        .iter()
+        .chain([&APR1_PREFIX, &SHA1_PREFIX])
+        .any(|prefix| stored.starts_with(prefix))
+}
+
+/// Checks the given password against an `.htpasswd` hash.
+///
+/// Returns `None` if the hash does not use a recognized scheme, and
+/// `Some(false)` if it does but is malformed or does not match.
+pub fn verify_htpasswd_hash(password: &str, stored: &str) -> Option<bool> {
+    if BCRYPT_PREFIXES
+        .iter()
+        .any(|prefix| stored.starts_with(prefix))
+    {
+        Some(bcrypt::verify(password, stored).unwrap_or(false))
+    } else if let Some(rest) = stored.strip_prefix(APR1_PREFIX) {
+        let salt = rest.split('$').next().unwrap_or("");
+        let expected = apr1_hash(password, salt);
+        Some(constant_time_eq(stored.as_bytes(), expected.as_bytes()).into())
+    } else if let Some(encoded) = stored.strip_prefix(SHA1_PREFIX) {
+        let digest = Sha1::digest(password.as_bytes());
+        Some(match STANDARD.decode(encoded) {
+            Ok(decoded) => decoded.ct_eq(digest.as_slice()).into(),
+            Err(_) => false,
+        })
+    } else {
+        None
+    }
+}
+
+/// Hash the given password with bcrypt at the given cost, returning a hash
+/// with the `$2y$` prefix preferred by Apache.
+///
+/// Returns an error if the cost is outside of the range bcrypt accepts, 4 to
+/// 31.
+pub fn hash_bcrypt(password: &str, cost: u32) -> Result<String, InvalidBcryptCost> {
+    let cost = check_bcrypt_cost(cost)?;
+    let hash = bcrypt::hash_with_result(password, cost).map_err(|_| InvalidBcryptCost)?;
+    Ok(hash.format_for_version(bcrypt::Version::TwoY))
+}
+
+/// Returns the cost if bcrypt accepts it.
+pub(crate) fn check_bcrypt_cost(cost: u32) -> Result<u32, InvalidBcryptCost> {
+    if (4..=31).contains(&cost) {
+        Ok(cost)
+    } else {
+        Err(InvalidBcryptCost)
+    }
+}
+
+/// The error returned by [`hash_bcrypt`] for a cost that bcrypt does not
+/// accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidBcryptCost;
+
+impl fmt::Display for InvalidBcryptCost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("bcrypt cost must be between 4 and 31")
+    }
+}
+
+impl Error for InvalidBcryptCost {}
+
+/// Hash the given password with Apache's MD5-crypt variant and a random
+/// salt, returning a hash with the `$apr1$` prefix.
+///
+/// This is weaker than bcrypt, and only meant for tools that require it.
+pub fn hash_apr1(password: &str) -> String {
+    let salt: String = (0..8)
+        .map(|_| char::from(CRYPT_ALPHABET[generate::random_index(CRYPT_ALPHABET.len())]))
+        .collect();
+    apr1_hash(password, &salt)
+}
+
+/// Computes an `$apr1$` hash of the password with the given salt.
+///
+/// This is the MD5-crypt algorithm with Apache's magic string; only the first
+/// 8 characters of the salt are used.
+fn apr1_hash(password: &str, salt: &str) -> String {
+    let password = password.as_bytes();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+
+    let alternate = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+
+    let mut context = Md5::new()
+        .chain_update(password)
+        .chain_update(APR1_PREFIX)
+        .chain_update(salt);
+    for chunk in password.chunks(16) {
+        context.update(&alternate[..chunk.len()]);
+    }
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            context.update([0]);
+        } else {
+            context.update(&password[..1]);
+        }
+        length >>= 1;
+    }
+    let mut result = context.finalize();
+
+    for round in 0..1000 {
+        let mut context = Md5::new();
+        if round & 1 == 1 {
+            context.update(password);
+        } else {
+            context.update(result);
+        }
+        if round % 3 != 0 {
+            context.update(salt);
+        }
+        if round % 7 != 0 {
+            context.update(password);
+        }
+        if round & 1 == 1 {
+            context.update(result);
+        } else {
+            context.update(password);
+        }
+        result = context.finalize();
+    }
+
+    let mut encoded = String::with_capacity(22);
+    let mut push = |mut value: u32, digits: usize| {
+        for _ in 0..digits {
+            encoded.push(CRYPT_ALPHABET[(value & 0x3f) as usize] as char);
+            value >>= 6;
+        }
+    };
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push(
+            (result[a] as u32) << 16 | (result[b] as u32) << 8 | result[c] as u32,
+            4,
+        );
+    }
+    push(result[11] as u32, 2);
+
+    format!(
+        "{}{}${}",
+        APR1_PREFIX,
+        String::from_utf8_lossy(salt),
+        encoded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::creds::{Creds, CredsError};
+
+    /// Made with `openssl passwd -apr1 -salt r31.7cvh secret`.
+    const APR1_LINE: &str = "alice:$apr1$r31.7cvh$h7vv2UKnPtM4DgYxbGEWO.";
+
+    /// A test vector of the OpenBSD bcrypt implementation, for the password
+    /// `U*U`.
+    const BCRYPT_LINE: &str = "bob:$2y$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW";
+
+    #[test]
+    fn apr1_matches_openssl() {
+        assert_eq!(
+            apr1_hash("secret", "r31.7cvh"),
+            "$apr1$r31.7cvh$h7vv2UKnPtM4DgYxbGEWO."
+        );
+        assert_eq!(
+            apr1_hash("password", "saltsalt"),
+            "$apr1$saltsalt$yAAkm4libquA.ZWLHbSBq/"
+        );
+    }
+
+    #[test]
+    fn verifies_known_htpasswd_lines() {
+        let contents = format!(
+            "{}\n{}\ncarol:{{SHA}}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n",
+            APR1_LINE, BCRYPT_LINE
+        );
+        let creds = Creds::read_htpasswd(contents.as_bytes()).unwrap();
+        assert!(creds.verify("alice", "secret"));
+        assert!(!creds.verify("alice", "Secret"));
+        assert!(creds.verify("bob", "U*U"));
+        assert!(!creds.verify("bob", "U*V"));
+        assert!(creds.verify("carol", "password"));
+        assert!(!creds.verify("carol", "passwore"));
+    }
+
+    #[test]
+    fn write_htpasswd_rejects_argon2_hashes() {
+        let mut creds = Creds::new();
+        creds.set_hash_params(crate::hash::HashParams::new(64, 1, 1).unwrap());
+        creds.insert_hashed("alice", "secret");
+        assert!(matches!(
+            creds.write_htpasswd(Vec::new()),
+            Err(CredsError::UnsupportedHash { username }) if username == "alice"
+        ));
+    }
+
+    #[test]
+    fn hash_apr1_uses_a_random_salt() {
+        let first = hash_apr1("secret");
+        assert!(first.starts_with(APR1_PREFIX));
+        assert_ne!(first, hash_apr1("secret"));
+        assert_eq!(verify_htpasswd_hash("secret", &first), Some(true));
+        assert_eq!(verify_htpasswd_hash("wrong", &first), Some(false));
+        assert_eq!(verify_htpasswd_hash("secret", "secret"), None);
+    }
+
+    #[test]
+    fn write_htpasswd_hashes_plaintext_with_bcrypt() {
+        let mut creds = Creds::read_htpasswd(APR1_LINE.as_bytes()).unwrap();
+        creds.insert("dave", "plain");
+        let mut out = Vec::new();
+        creds.write_htpasswd_bcrypt(&mut out, 4).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.starts_with(&format!("{}\ndave:$2y$04$", APR1_LINE)),
+            "{}",
+            out
+        );
+
+        let read = Creds::read_htpasswd(out.as_bytes()).unwrap();
+        assert!(read.verify("alice", "secret"));
+        assert!(read.verify("dave", "plain"));
+    }
+
+    #[test]
+    fn hash_bcrypt_rejects_invalid_costs() {
+        assert_eq!(hash_bcrypt("secret", 3), Err(InvalidBcryptCost));
+        assert_eq!(hash_bcrypt("secret", 32), Err(InvalidBcryptCost));
+        let hash = hash_bcrypt("secret", 4).unwrap();
+        assert!(hash.starts_with("$2y$04$"));
+        assert_eq!(verify_htpasswd_hash("secret", &hash), Some(true));
+    }
+}
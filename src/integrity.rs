@@ -0,0 +1,269 @@
+//! Detecting changes to credential files.
+//!
+//! Two kinds of checks are available. A SHA-256 checksum line, enabled with
+//! [`WriteOptions::checksum`](crate::format::WriteOptions::checksum) and
+//! [`ReadOptions::verify_checksum`](crate::format::ReadOptions::verify_checksum), detects
+//! accidental truncation or corruption. An HMAC footer, computed with a secret
+//! key, also detects deliberate changes by anyone who does not have the key.
+//!
+//! A file written with [`Creds::write_with_hmac`] is a regular credentials
+//! file followed by one more line:
+//!
+//! ```text
+//! #hmac-sha256:<hex>
+//! ```
+//!
+//! where `<hex>` is the HMAC-SHA256 of every byte before that line, in
+//! lowercase hexadecimal. The footer is a comment, so readers that do not
+//! check it still read the same credentials. [`Creds::read_verified`] checks
+//! the footer before parsing anything.
+//!
+//! Unlike [encryption](crate::encrypt), this does not hide the passwords; it
+//! only detects changes made without the key.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Read, Write},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{
+    creds::{Creds, CredsError},
+    format::{LineEnding, WriteOptions},
+};
+
+const HMAC_PREFIX: &str = "#hmac-sha256:";
+const CHECKSUM_PREFIX: &str = "sha256:";
+
+pub(crate) type HmacSha256 = Hmac<Sha256>;
+
+impl Creds {
+    /// Writes the credentials into the given writer, followed by an
+    /// HMAC-SHA256 footer computed with the given key.
+    ///
+    /// See the [module documentation](crate::integrity) for the format. The
+    /// credentials must be representable in the plain format; see
+    /// [`Creds::write`].
+    pub fn write_with_hmac(&self, mut writer: impl Write, key: &[u8]) -> Result<(), CredsError> {
+        let mut contents = Zeroizing::new(Vec::new());
+        WriteOptions::new().write(self, &mut *contents)?;
+
+        let footer = hmac_footer(mac(key, &contents));
+        writer.write_all(&contents)?;
+        writer.write_all(footer.as_bytes())?;
+        Ok(())
+    }
+
+    /// Parses credentials from the given reader, after checking its
+    /// HMAC-SHA256 footer with the given key.
+    ///
+    /// Returns [`CredsError::Integrity`] if the key is wrong, or if the input
+    /// does not end with a footer or has been modified. Nothing is parsed
+    /// unless the check passes.
+    pub fn read_verified(mut reader: impl Read, key: &[u8]) -> Result<Self, CredsError> {
+        let mut contents = Zeroizing::new(Vec::new());
+        reader.read_to_end(&mut contents)?;
+
+        let end = contents.strip_suffix(b"\n").unwrap_or(&contents);
+        let footer_start = end
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |index| index + 1);
+        let (body, footer) = contents.split_at(footer_start);
+        let tag = footer
+            .strip_prefix(HMAC_PREFIX.as_bytes())
+            .map(|footer| footer.strip_suffix(b"\n").unwrap_or(footer))
+            .and_then(decode_hex)
+            .ok_or(CredsError::Integrity)?;
+        mac(key, body)
+            .verify_slice(&tag)
+            .map_err(|_| CredsError::Integrity)?;
+
+        Self::read(body)
+    }
+}
+
+/// Returns the HMAC footer line for the contents hashed by `mac`.
+pub(crate) fn hmac_footer(mac: HmacSha256) -> String {
+    let tag = mac.finalize().into_bytes();
+    format!("{}{}\n", HMAC_PREFIX, encode_hex(&tag))
+}
+
+/// Returns the checksum line for contents with the given hash.
+pub(crate) fn checksum_line(comment: char, digest: Sha256) -> String {
+    format!(
+        "{}{}{}\n",
+        comment,
+        CHECKSUM_PREFIX,
+        encode_hex(&digest.finalize())
+    )
+}
+
+/// A writer that hashes everything written through it.
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    digest: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            digest: Sha256::new(),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Sha256 {
+        self.digest
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.digest.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Verifies the checksum line at the end of a file, given its lines one by
+/// one.
+pub(crate) struct ChecksumReader {
+    prefix: String,
+    digest: Sha256,
+    /// The checksum on the last line, if that was a checksum line, and the
+    /// hash of the lines before it.
+    last: Option<(Option<Vec<u8>>, Sha256)>,
+}
+
+impl ChecksumReader {
+    pub(crate) fn new(comment: char) -> Self {
+        Self {
+            prefix: format!("{}{}", comment, CHECKSUM_PREFIX),
+            digest: Sha256::new(),
+            last: None,
+        }
+    }
+
+    pub(crate) fn update(&mut self, line: &str, ending: LineEnding) {
+        self.last = line
+            .strip_prefix(&self.prefix)
+            .map(|hex| (decode_hex(hex.as_bytes()), self.digest.clone()));
+        self.digest.update(line.as_bytes());
+        self.digest.update(ending.as_str().as_bytes());
+    }
+
+    pub(crate) fn finish(self) -> Result<(), CredsError> {
+        match self.last {
+            Some((expected, digest)) => match expected {
+                Some(expected) if *expected == *digest.finalize() => Ok(()),
+                _ => Err(CredsError::ChecksumMismatch),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn mac(key: &[u8], contents: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(contents);
+    mac
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"integrity test key";
+
+    fn signed() -> (Creds, Vec<u8>) {
+        let creds: Creds = [("alice", "secret"), ("bob", "hunter2")]
+            .into_iter()
+            .collect();
+        let mut contents = Vec::new();
+        creds.write_with_hmac(&mut contents, KEY).unwrap();
+        (creds, contents)
+    }
+
+    #[test]
+    fn valid_files_read_back() {
+        let (creds, contents) = signed();
+        let text = std::str::from_utf8(&contents).unwrap();
+        let footer = text.lines().last().unwrap();
+        assert!(footer.starts_with(HMAC_PREFIX));
+        assert_eq!(footer.len(), HMAC_PREFIX.len() + 64);
+
+        assert_eq!(Creds::read_verified(&contents[..], KEY).unwrap(), creds);
+        // Readers that do not check the footer skip it as a comment.
+        assert_eq!(Creds::read(&contents[..]).unwrap(), creds);
+    }
+
+    #[test]
+    fn flipped_bytes_are_rejected() {
+        let (_, contents) = signed();
+        for index in 0..contents.len() - 1 {
+            let mut tampered = contents.clone();
+            tampered[index] ^= 0x01;
+            assert!(
+                matches!(
+                    Creds::read_verified(&tampered[..], KEY),
+                    Err(CredsError::Integrity)
+                ),
+                "flipped byte {} was accepted",
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_keys_are_rejected() {
+        let (_, contents) = signed();
+        assert!(matches!(
+            Creds::read_verified(&contents[..], b"another key"),
+            Err(CredsError::Integrity)
+        ));
+        assert!(matches!(
+            Creds::read_verified(&contents[..], b""),
+            Err(CredsError::Integrity)
+        ));
+    }
+
+    #[test]
+    fn missing_footers_are_rejected() {
+        assert!(matches!(
+            Creds::read_verified(&b"alice:secret\n"[..], KEY),
+            Err(CredsError::Integrity)
+        ));
+        assert!(matches!(
+            Creds::read_verified(&b""[..], KEY),
+            Err(CredsError::Integrity)
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+//! JSON import and export, enabled by the `json` feature.
+//!
+//! This uses the [`serde`](crate::serde_impl) representation: a JSON object
+//! mapping usernames to passwords, in insertion order.
+
+use std::io::{self, Read, Write};
+
+use crate::creds::{Creds, CredsError};
+
+impl Creds {
+    /// Serializes the store as a JSON object mapping usernames to passwords,
+    /// as in `{"alice":"hunter2"}`.
+    ///
+    /// Unlike the line format, JSON can represent any username and password,
+    /// including ones with colons, newlines, or a leading `#`, so this never
+    /// fails.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a map of strings is always valid JSON")
+    }
+
+    /// Parses a store from a JSON object mapping usernames to passwords.
+    ///
+    /// Invalid JSON is reported as an [`InvalidData`](std::io::ErrorKind::InvalidData)
+    /// [`CredsError::Io`] error. As with [`Creds::read`], a later entry
+    /// overwrites an earlier one with the same username.
+    pub fn from_json(json: &str) -> Result<Self, CredsError> {
+        serde_json::from_str(json).map_err(json_error)
+    }
+
+    /// Writes the store into the given writer as a JSON object.
+    ///
+    /// See also: [`Creds::to_json`]
+    pub fn write_json(&self, writer: impl Write) -> Result<(), CredsError> {
+        serde_json::to_writer(writer, self).map_err(json_error)
+    }
+
+    /// Parses a store from a JSON object in the given reader.
+    ///
+    /// Errors from the reader itself are reported as they are.
+    ///
+    /// See also: [`Creds::from_json`]
+    pub fn read_json(reader: impl Read) -> Result<Self, CredsError> {
+        serde_json::from_reader(reader).map_err(json_error)
+    }
+}
+
+/// Converts a JSON error into an [`InvalidData`](io::ErrorKind::InvalidData)
+/// error, unless it came from the underlying reader or writer.
+///
+/// `serde_json` itself reports truncated input as
+/// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof), which would look like an
+/// I/O error rather than invalid JSON.
+fn json_error(error: serde_json::Error) -> CredsError {
+    if error.is_io() {
+        CredsError::Io(error.into())
+    } else {
+        CredsError::Io(io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn awkward() -> Creds {
+        [
+            ("user:name", "pass:word"),
+            ("multi\nline", "line1\nline2\r\n"),
+            ("#hash", "#not a comment"),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn round_trips_what_the_line_format_cannot_represent() {
+        let creds = awkward();
+        let json = creds.to_json();
+        assert_eq!(
+            json,
+            r##"{"user:name":"pass:word","multi\nline":"line1\nline2\r\n","#hash":"#not a comment"}"##
+        );
+        assert_eq!(Creds::from_json(&json).unwrap(), creds);
+
+        let mut written = Vec::new();
+        creds.write_json(&mut written).unwrap();
+        assert_eq!(written, json.as_bytes());
+        let read = Creds::read_json(&written[..]).unwrap();
+        assert_eq!(read, creds);
+        assert_eq!(
+            read.usernames().collect::<Vec<_>>(),
+            ["user:name", "multi\nline", "#hash"]
+        );
+    }
+
+    #[test]
+    fn invalid_json_is_an_invalid_data_error() {
+        for json in ["", "{", r#"["alice","a"]"#, r#"{"alice":1}"#, "alice:a\n"] {
+            let error = Creds::from_json(json).unwrap_err();
+            assert!(
+                matches!(&error, CredsError::Io(error) if error.kind() == io::ErrorKind::InvalidData),
+                "{:?}: {:?}",
+                json,
+                error
+            );
+            assert!(matches!(
+                Creds::read_json(json.as_bytes()),
+                Err(CredsError::Io(error)) if error.kind() == io::ErrorKind::InvalidData
+            ));
+        }
+    }
+}
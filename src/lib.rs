@@ -1 +1,30 @@
+pub mod builder;
 pub mod creds;
+pub mod document;
+pub mod encrypt;
+pub mod format;
+pub mod generate;
+pub mod hash;
+pub mod hasher;
+pub mod htpasswd;
+pub mod integrity;
+pub mod lock;
+pub mod meta;
+pub mod policy;
+pub mod shared;
+pub mod stream;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+#[cfg(feature = "csv")]
+mod csv_impl;
+mod file;
+#[cfg(feature = "flate2")]
+mod gzip_impl;
+#[cfg(feature = "json")]
+mod json_impl;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "tokio")]
+mod tokio_impl;
+mod username;
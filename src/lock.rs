@@ -0,0 +1,199 @@
+//! Locking credential files against concurrent edits by other processes.
+//!
+//! Two processes that read a file, change it, and write it back at the same
+//! time can lose each other's changes. A [`LockedCreds`] holds an exclusive
+//! lock for a whole edit session, so such edits happen one after another.
+//!
+//! The locks are taken on a separate lock file next to the credentials file,
+//! named like it with `.lock` appended, because
+//! [`Creds::write_to_file`](crate::creds::Creds::write_to_file) replaces the
+//! credentials file rather than modifying it. The lock file is left in place
+//! afterwards.
+//!
+//! The locks are advisory: they only exclude other processes that lock the
+//! same file, through this module or otherwise, and do not stop anyone from
+//! reading or writing the credentials file directly. They are taken with
+//! `flock` on Unix and `LockFileEx` on Windows. On Unix they may not work on
+//! network filesystems like NFS, depending on the system.
+
+use std::{
+    fmt,
+    fs::File,
+    io,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    creds::{Creds, CredsError},
+    file,
+};
+
+/// A credential store read from a file, with an exclusive lock on the file.
+///
+/// The lock is held until the `LockedCreds` is dropped, and other processes
+/// calling [`LockedCreds::open`] or [`Creds::read_from_file_locked`] on the
+/// same path wait until then. The store can be used through [`Deref`] and
+/// [`DerefMut`], and changes are written back with [`LockedCreds::save`].
+pub struct LockedCreds {
+    creds: Creds,
+    path: PathBuf,
+    _lock: File,
+}
+
+impl LockedCreds {
+    /// Locks the given file and reads its credentials, blocking until no other
+    /// process holds the lock.
+    ///
+    /// A file that does not exist yet is treated as empty, so that it can be
+    /// created with [`LockedCreds::save`].
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CredsError> {
+        let path = path.as_ref().to_owned();
+        let lock = file::lock_exclusive(&path)?;
+        let creds = match Creds::read_from_file(&path) {
+            Err(CredsError::Io(error)) if error.kind() == io::ErrorKind::NotFound => Creds::new(),
+            result => result?,
+        };
+        Ok(Self {
+            creds,
+            path,
+            _lock: lock,
+        })
+    }
+
+    /// Writes the credentials back to the file, keeping the lock.
+    ///
+    /// See also: [`Creds::write_to_file`]
+    pub fn save(&self) -> Result<(), CredsError> {
+        self.creds.write_to_file(&self.path)
+    }
+
+    /// Returns the path of the credentials file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Releases the lock, returning the credentials.
+    ///
+    /// Unsaved changes are not written.
+    pub fn into_creds(self) -> Creds {
+        self.creds
+    }
+}
+
+impl Deref for LockedCreds {
+    type Target = Creds;
+
+    fn deref(&self) -> &Creds {
+        &self.creds
+    }
+}
+
+impl DerefMut for LockedCreds {
+    fn deref_mut(&mut self) -> &mut Creds {
+        &mut self.creds
+    }
+}
+
+impl fmt::Debug for LockedCreds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockedCreds")
+            .field("creds", &self.creds)
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Creds {
+    /// Parses a credential store from the given file, with a shared lock on
+    /// the file while reading.
+    ///
+    /// This waits for any [`LockedCreds`] session on the file to finish, so it
+    /// does not see changes in the middle of one. See the
+    /// [module documentation](crate::lock) for how locking works.
+    ///
+    /// Only read access to the lock file is needed, so this works for users
+    /// that may read the credentials but not change them. If the lock file
+    /// does not exist and cannot be created, the file is read without a lock,
+    /// since no session can be holding one.
+    pub fn read_from_file_locked(path: impl AsRef<Path>) -> Result<Self, CredsError> {
+        let path = path.as_ref();
+        let _lock = file::lock_shared(path)?;
+        Self::read_from_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::mpsc, thread, time::Duration};
+
+    use super::*;
+    use crate::file::TestDir;
+
+    #[test]
+    fn save_writes_the_changes() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        let mut locked = LockedCreds::open(&path).unwrap();
+        assert!(locked.is_empty());
+        locked.insert("alice", "a");
+        locked.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "alice:a\n");
+
+        locked.insert("bob", "b");
+        drop(locked);
+        let read = Creds::read_from_file_locked(&path).unwrap();
+        assert!(read.verify("alice", "a"));
+        assert!(!read.contains("bob"));
+    }
+
+    #[test]
+    fn other_locks_wait_for_the_session() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        let mut locked = LockedCreds::open(&path).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let exclusive = {
+            let (path, sender) = (path.clone(), sender.clone());
+            thread::spawn(move || {
+                let locked = LockedCreds::open(&path).unwrap();
+                sender.send(("exclusive", locked.len())).unwrap();
+            })
+        };
+        let shared = thread::spawn(move || {
+            let read = Creds::read_from_file_locked(&path).unwrap();
+            sender.send(("shared", read.len())).unwrap();
+        });
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+
+        locked.insert("alice", "a");
+        locked.save().unwrap();
+        drop(locked);
+        let mut results = [
+            receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+            receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+        ];
+        results.sort();
+        assert_eq!(results, [("exclusive", 1), ("shared", 1)]);
+        exclusive.join().unwrap();
+        shared.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_locks_only_need_read_access() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        fs::write(&path, "alice:a\n").unwrap();
+        drop(LockedCreds::open(&path).unwrap());
+        let lock_path = dir.join("creds.lock");
+        fs::set_permissions(&lock_path, fs::Permissions::from_mode(0o400)).unwrap();
+
+        let read = Creds::read_from_file_locked(&path).unwrap();
+        assert!(read.verify("alice", "a"));
+        assert_eq!(dir.file_names(), ["creds", "creds.lock"]);
+    }
+}
@@ -0,0 +1,247 @@
+//! Metadata about each credential, such as when it was last changed.
+//!
+//! Every credential in a [`Creds`](crate::creds::Creds) store has a
+//! [`CredMeta`], returned by [`Creds::metadata`](crate::creds::Creds::metadata)
+//! and changed through [`Creds::metadata_mut`](crate::creds::Creds::metadata_mut).
+//! Inserting a password through the store records the time.
+//!
+//! Metadata is only written to and read from credential files when enabled
+//! with [`WriteOptions::metadata`](crate::format::WriteOptions::metadata) and
+//! [`ReadOptions::metadata`](crate::format::ReadOptions::metadata). This
+//! includes the expiry time, so an expired credential verifies again if its
+//! file is written or read without metadata. It is written as a comment line
+//! right before the entry it describes:
+//!
+//! ```text
+//! #meta created=1700000000 modified=1700003600 expires=1800000000 note=temporary%20access
+//! alice:hunter2
+//! ```
+//!
+//! Times are whole seconds since the Unix epoch, and entries without an
+//! `expires` time never expire. Notes are percent-encoded. Readers without metadata
+//! support skip these lines like any other comment, so such files remain
+//! readable as plain credentials files.
+
+use std::{
+    fmt::Write as _,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const META_PREFIX: &str = "meta";
+
+/// Metadata about a single credential.
+///
+/// Every field is optional, since credentials read from files without
+/// metadata have none.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CredMeta {
+    created: Option<SystemTime>,
+    modified: Option<SystemTime>,
+    expires: Option<SystemTime>,
+    note: Option<String>,
+}
+
+impl CredMeta {
+    /// Returns when the credential was first inserted, if known.
+    pub fn created(&self) -> Option<SystemTime> {
+        self.created
+    }
+
+    /// Returns when the password was last set, if known.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// Returns when the credential stops being accepted by
+    /// [`Creds::verify`](crate::creds::Creds::verify), if it expires.
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    /// Sets when the credential expires, or `None` for never.
+    ///
+    /// See [`Creds::insert_with_expiry`](crate::creds::Creds::insert_with_expiry).
+    pub fn set_expires(&mut self, expires: Option<SystemTime>) {
+        self.expires = expires;
+    }
+
+    /// Returns the free-form note attached to the credential, if any.
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+
+    /// Sets the note attached to the credential, or removes it with `None`.
+    ///
+    /// Notes may contain any characters, including newlines.
+    pub fn set_note(&mut self, note: Option<String>) {
+        self.note = note;
+    }
+
+    /// Returns `true` if the credential expires at or before `now`.
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Returns `true` if no metadata is known.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the metadata of a credential inserted at `now`.
+    pub(crate) fn new_at(now: SystemTime) -> Self {
+        Self {
+            created: Some(now),
+            modified: Some(now),
+            expires: None,
+            note: None,
+        }
+    }
+
+    /// Records that the password was changed at `now`.
+    pub(crate) fn touch(&mut self, now: SystemTime) {
+        self.modified = Some(now);
+    }
+
+    /// Formats the metadata as a comment line, without a line ending.
+    pub(crate) fn to_line(&self, comment: char) -> String {
+        let mut line = format!("{}{}", comment, META_PREFIX);
+        for (key, value) in [
+            ("created", self.created),
+            ("modified", self.modified),
+            ("expires", self.expires),
+        ] {
+            if let Some(seconds) = value.and_then(to_seconds) {
+                let _ = write!(line, " {}={}", key, seconds);
+            }
+        }
+        if let Some(note) = &self.note {
+            let _ = write!(line, " note={}", encode_note(note));
+        }
+        line
+    }
+
+    /// Parses a metadata comment line, as written by [`CredMeta::to_line`].
+    ///
+    /// Returns `None` if the line is not a metadata line. Unknown keys and
+    /// invalid values are ignored, so that newer files can still be read.
+    pub(crate) fn parse_line(line: &str, comment: char) -> Option<Self> {
+        let fields = line
+            .trim_start()
+            .strip_prefix(comment)?
+            .strip_prefix(META_PREFIX)?;
+        if !fields.is_empty() && !fields.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let mut meta = Self::default();
+        for field in fields.split_whitespace() {
+            let (key, value) = match field.split_once('=') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let time = || value.parse().ok().and_then(from_seconds);
+            match key {
+                "created" => meta.created = time(),
+                "modified" => meta.modified = time(),
+                "expires" => meta.expires = time(),
+                "note" => meta.note = decode_note(value),
+                _ => {}
+            }
+        }
+        Some(meta)
+    }
+}
+
+/// Percent-encodes a note, so that it has no whitespace or control
+/// characters.
+fn encode_note(note: &str) -> String {
+    let mut encoded = String::with_capacity(note.len());
+    for c in note.chars() {
+        if c == '%' || c.is_whitespace() || c.is_control() {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        } else {
+            encoded.push(c);
+        }
+    }
+    encoded
+}
+
+fn decode_note(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail
+                .get(..2)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn to_seconds(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+fn from_seconds(seconds: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_round_trip() {
+        let mut meta = CredMeta::new_at(from_seconds(1_700_000_000).unwrap());
+        meta.touch(from_seconds(1_700_003_600).unwrap());
+        meta.set_expires(from_seconds(1_800_000_000));
+        meta.set_note(Some("temporary access\n100%".to_owned()));
+
+        let line = meta.to_line('#');
+        assert_eq!(
+            line,
+            "#meta created=1700000000 modified=1700003600 expires=1800000000 \
+             note=temporary%20access%0A100%25"
+        );
+        assert_eq!(CredMeta::parse_line(&line, '#'), Some(meta));
+    }
+
+    #[test]
+    fn parsing_ignores_unknown_and_invalid_fields() {
+        let meta = CredMeta::parse_line("#meta created=x future=1 modified=5 stray", '#').unwrap();
+        assert_eq!(meta.created(), None);
+        assert_eq!(meta.modified(), from_seconds(5));
+        assert_eq!(
+            CredMeta::parse_line("#meta", '#'),
+            Some(CredMeta::default())
+        );
+    }
+
+    #[test]
+    fn other_comments_are_not_metadata() {
+        assert_eq!(CredMeta::parse_line("# meta created=1", '#'), None);
+        assert_eq!(CredMeta::parse_line("#metadata", '#'), None);
+        assert_eq!(CredMeta::parse_line("alice:meta", '#'), None);
+        assert_eq!(CredMeta::parse_line(";meta created=1", '#'), None);
+    }
+
+    #[test]
+    fn expiry_is_inclusive() {
+        let mut meta = CredMeta::default();
+        let now = from_seconds(1000).unwrap();
+        assert!(!meta.is_expired(now));
+        meta.set_expires(Some(now));
+        assert!(meta.is_expired(now));
+        assert!(!meta.is_expired(now - Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,239 @@
+//! Password strength rules.
+//!
+//! A [`PasswordPolicy`] is stored on each [`Creds`](crate::creds::Creds)
+//! store and checked by [`Creds::insert_checked`](crate::creds::Creds::insert_checked).
+//! Policies can also be used on their own with [`PasswordPolicy::validate`],
+//! or passed to [`Creds::insert_checked_with`](crate::creds::Creds::insert_checked_with).
+
+use std::{error::Error, fmt};
+
+/// A set of rules that passwords must follow.
+///
+/// The default policy has no rules, so it accepts every password. Rules are
+/// added with the builder methods, as in
+/// `PasswordPolicy::new().min_length(12).require_digit(true)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    require_uppercase: bool,
+    require_lowercase: bool,
+    require_digit: bool,
+    require_symbol: bool,
+}
+
+impl PasswordPolicy {
+    /// Create a policy without any rules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the minimum number of characters in a password.
+    ///
+    /// Defaults to 0. Characters are counted as Unicode scalar values, not
+    /// bytes.
+    pub fn min_length(mut self, min_length: usize) -> Self {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Sets whether a password must contain an uppercase letter.
+    pub fn require_uppercase(mut self, require: bool) -> Self {
+        self.require_uppercase = require;
+        self
+    }
+
+    /// Sets whether a password must contain a lowercase letter.
+    pub fn require_lowercase(mut self, require: bool) -> Self {
+        self.require_lowercase = require;
+        self
+    }
+
+    /// Sets whether a password must contain a digit.
+    pub fn require_digit(mut self, require: bool) -> Self {
+        self.require_digit = require;
+        self
+    }
+
+    /// Sets whether a password must contain a symbol, which is any character
+    /// that is neither alphanumeric nor whitespace.
+    pub fn require_symbol(mut self, require: bool) -> Self {
+        self.require_symbol = require;
+        self
+    }
+
+    /// Checks a password against every rule of the policy.
+    ///
+    /// On failure, the returned [`PolicyViolation`] lists all the rules that
+    /// the password breaks, not just the first one.
+    pub fn validate(&self, password: &str) -> Result<(), PolicyViolation> {
+        let has = |class: fn(char) -> bool| password.chars().any(class);
+        let mut failed = Vec::new();
+        if password.chars().count() < self.min_length {
+            failed.push(Rule::MinLength(self.min_length));
+        }
+        if self.require_uppercase && !has(char::is_uppercase) {
+            failed.push(Rule::Uppercase);
+        }
+        if self.require_lowercase && !has(char::is_lowercase) {
+            failed.push(Rule::Lowercase);
+        }
+        if self.require_digit && !has(char::is_numeric) {
+            failed.push(Rule::Digit);
+        }
+        if self.require_symbol && !has(|c| !c.is_alphanumeric() && !c.is_whitespace()) {
+            failed.push(Rule::Symbol);
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(PolicyViolation { failed })
+        }
+    }
+}
+
+/// A single rule of a [`PasswordPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// The password must have at least this many characters.
+    MinLength(usize),
+    /// The password must contain an uppercase letter.
+    Uppercase,
+    /// The password must contain a lowercase letter.
+    Lowercase,
+    /// The password must contain a digit.
+    Digit,
+    /// The password must contain a symbol.
+    Symbol,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MinLength(min_length) => write!(f, "at least {} characters", min_length),
+            Self::Uppercase => f.write_str("an uppercase letter"),
+            Self::Lowercase => f.write_str("a lowercase letter"),
+            Self::Digit => f.write_str("a digit"),
+            Self::Symbol => f.write_str("a symbol"),
+        }
+    }
+}
+
+/// The error type for a password that does not follow a [`PasswordPolicy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    failed: Vec<Rule>,
+}
+
+impl PolicyViolation {
+    /// Returns the rules that the password breaks, in the order they are
+    /// listed in [`Rule`].
+    pub fn failed(&self) -> &[Rule] {
+        &self.failed
+    }
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("password must contain ")?;
+        for (index, rule) in self.failed.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            rule.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for PolicyViolation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::creds::Creds;
+
+    fn failed(policy: &PasswordPolicy, password: &str) -> Vec<Rule> {
+        policy
+            .validate(password)
+            .err()
+            .map(|violation| violation.failed().to_vec())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn empty_policy_accepts_everything() {
+        assert_eq!(PasswordPolicy::new().validate(""), Ok(()));
+    }
+
+    #[test]
+    fn min_length_counts_characters() {
+        let policy = PasswordPolicy::new().min_length(4);
+        assert_eq!(failed(&policy, "abc"), [Rule::MinLength(4)]);
+        assert_eq!(failed(&policy, "abcd"), []);
+        assert_eq!(failed(&policy, "äöüß"), []);
+    }
+
+    #[test]
+    fn requires_uppercase() {
+        let policy = PasswordPolicy::new().require_uppercase(true);
+        assert_eq!(failed(&policy, "abc"), [Rule::Uppercase]);
+        assert_eq!(failed(&policy, "aBc"), []);
+    }
+
+    #[test]
+    fn requires_lowercase() {
+        let policy = PasswordPolicy::new().require_lowercase(true);
+        assert_eq!(failed(&policy, "ABC"), [Rule::Lowercase]);
+        assert_eq!(failed(&policy, "AbC"), []);
+    }
+
+    #[test]
+    fn requires_digit() {
+        let policy = PasswordPolicy::new().require_digit(true);
+        assert_eq!(failed(&policy, "abc"), [Rule::Digit]);
+        assert_eq!(failed(&policy, "ab1"), []);
+    }
+
+    #[test]
+    fn requires_symbol() {
+        let policy = PasswordPolicy::new().require_symbol(true);
+        assert_eq!(failed(&policy, "ab c"), [Rule::Symbol]);
+        assert_eq!(failed(&policy, "ab!c"), []);
+    }
+
+    #[test]
+    fn reports_every_broken_rule() {
+        let policy = PasswordPolicy::new()
+            .min_length(12)
+            .require_uppercase(true)
+            .require_lowercase(true)
+            .require_digit(true)
+            .require_symbol(true);
+        let violation = policy.validate("abc").unwrap_err();
+        assert_eq!(
+            violation.failed(),
+            [
+                Rule::MinLength(12),
+                Rule::Uppercase,
+                Rule::Digit,
+                Rule::Symbol
+            ]
+        );
+        assert_eq!(
+            violation.to_string(),
+            "password must contain at least 12 characters, an uppercase letter, a digit, a symbol"
+        );
+        assert_eq!(policy.validate("correct-Horse-battery-9"), Ok(()));
+    }
+
+    #[test]
+    fn insert_checked_uses_the_store_policy() {
+        let mut creds = Creds::new();
+        creds.set_password_policy(PasswordPolicy::new().min_length(8));
+        let violation = creds.insert_checked("alice", "short").unwrap_err();
+        assert_eq!(violation.failed(), [Rule::MinLength(8)]);
+        assert!(!creds.contains("alice"));
+        creds.insert_checked("alice", "long enough").unwrap();
+        assert!(creds.verify("alice", "long enough"));
+    }
+}
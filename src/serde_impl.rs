@@ -0,0 +1,76 @@
+//! [`Serialize`] and [`Deserialize`] implementations, enabled by the `serde`
+//! feature.
+//!
+//! A [`Creds`] store is represented as a map from usernames to passwords, for
+//! example the JSON object `{"alice": "hunter2"}`. Entries are serialized in
+//! insertion order, and deserialized in the order they appear.
+
+use std::fmt;
+
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{creds::Creds, meta::CredMeta};
+
+impl Serialize for Creds {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (username, password) in self {
+            map.serialize_entry(username, password)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Creds {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(CredsVisitor)
+    }
+}
+
+struct CredsVisitor;
+
+impl<'de> Visitor<'de> for CredsVisitor {
+    type Value = Creds;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of usernames to password strings")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut creds = Creds::new();
+        while let Some((username, password)) = access.next_entry::<String, String>()? {
+            creds.insert_read(username, password, CredMeta::default());
+        }
+        Ok(creds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_as_an_ordered_json_object() {
+        let mut creds = Creds::new();
+        creds.insert("zed", "z");
+        creds.insert("alice", "hunter2");
+        let json = serde_json::to_string(&creds).unwrap();
+        assert_eq!(json, r#"{"zed":"z","alice":"hunter2"}"#);
+
+        let read: Creds = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            read.iter().collect::<Vec<_>>(),
+            [("zed", "z"), ("alice", "hunter2")]
+        );
+    }
+
+    #[test]
+    fn rejects_non_string_passwords() {
+        let error = serde_json::from_str::<Creds>(r#"{"alice":1}"#).unwrap_err();
+        assert!(error.to_string().contains("expected a string"), "{}", error);
+    }
+}
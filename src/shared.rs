@@ -0,0 +1,169 @@
+//! A credential store that can be shared between threads.
+
+use std::{
+    path::Path,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::creds::{Creds, CredsError};
+
+/// A handle to a [`Creds`] store behind a reader-writer lock.
+///
+/// Cloning the handle is cheap, and all clones share the same store, so it
+/// can be handed to every thread or request handler that needs it.
+///
+/// Each method takes the lock once, for the duration of that call only:
+/// [`get`](SharedCreds::get), [`verify`](SharedCreds::verify), and
+/// [`contains`](SharedCreds::contains) take a shared read lock and can run
+/// concurrently, while [`insert`](SharedCreds::insert),
+/// [`remove`](SharedCreds::remove), and
+/// [`reload_from_file`](SharedCreds::reload_from_file) take an exclusive
+/// write lock. To do several operations under one lock, use
+/// [`read`](SharedCreds::read) or [`write`](SharedCreds::write).
+///
+/// A panic while the lock is held does not make the store unusable; later
+/// calls still see it as the panicking thread left it.
+#[derive(Debug, Clone, Default)]
+pub struct SharedCreds {
+    inner: Arc<RwLock<Creds>>,
+}
+
+impl SharedCreds {
+    /// Wraps the given store for sharing.
+    pub fn new(creds: Creds) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(creds)),
+        }
+    }
+
+    /// Locks the store for reading, blocking until no thread is writing.
+    pub fn read(&self) -> RwLockReadGuard<'_, Creds> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks the store for writing, blocking until no other thread holds the
+    /// lock.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Creds> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Retrieve a copy of the stored password for the given username.
+    ///
+    /// The password is copied so that the lock is not held after returning.
+    /// See also: [`Creds::get`]
+    pub fn get(&self, username: &str) -> Option<String> {
+        self.read().get(username).map(str::to_owned)
+    }
+
+    /// Check if the given username exists in the store.
+    ///
+    /// See also: [`Creds::contains`]
+    pub fn contains(&self, username: &str) -> bool {
+        self.read().contains(username)
+    }
+
+    /// Verify that the given password matches the one stored for the given
+    /// username.
+    ///
+    /// See also: [`Creds::verify`]
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        self.read().verify(username, password)
+    }
+
+    /// Add a new username and password pair.
+    ///
+    /// See also: [`Creds::insert`]
+    pub fn insert(&self, username: impl Into<String>, password: impl Into<String>) {
+        self.write().insert(username, password)
+    }
+
+    /// Remove the given username from the store, returning its password.
+    ///
+    /// See also: [`Creds::remove`]
+    pub fn remove(&self, username: &str) -> Option<String> {
+        self.write().remove(username)
+    }
+
+    /// Replaces the credentials with the ones parsed from the given file.
+    ///
+    /// The file is parsed [strictly](Creds::read_strict) before taking the
+    /// lock, and other threads see either the old credentials or the new
+    /// ones, never a mix. If parsing fails, including because of a malformed
+    /// line, the store is left unchanged. The file is read with the store's
+    /// [delimiter](Creds::set_delimiter).
+    ///
+    /// See also: [`Creds::reload_from_file`]
+    pub fn reload_from_file(&self, path: impl AsRef<Path>) -> Result<(), CredsError> {
+        let options = self.read().read_options().strict(true);
+        let fresh = options.read_from_file(path)?;
+        self.write().replace_entries(fresh);
+        Ok(())
+    }
+}
+
+impl From<Creds> for SharedCreds {
+    fn from(creds: Creds) -> Self {
+        Self::new(creds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread};
+
+    use super::*;
+    use crate::file::TestDir;
+
+    #[test]
+    fn threads_verify_concurrently() {
+        let shared: SharedCreds = (0..8)
+            .map(|index| (format!("user{}", index), format!("pw{}", index)))
+            .collect::<Creds>()
+            .into();
+
+        let handles: Vec<_> = (0..8)
+            .map(|index| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    let username = format!("user{}", index);
+                    for _ in 0..100 {
+                        assert!(shared.verify(&username, &format!("pw{}", index)));
+                        assert!(!shared.verify(&username, "wrong"));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn writes_are_seen_by_every_clone() {
+        let shared = SharedCreds::default();
+        let writer = shared.clone();
+        thread::spawn(move || writer.insert("alice", "a"))
+            .join()
+            .unwrap();
+        assert!(shared.verify("alice", "a"));
+        assert_eq!(shared.remove("alice").as_deref(), Some("a"));
+        assert!(!shared.contains("alice"));
+    }
+
+    #[test]
+    fn reload_from_malformed_file_keeps_entries() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        fs::write(&path, "no delimiter here\n").unwrap();
+
+        let shared = SharedCreds::default();
+        shared.insert("alice", "a");
+        assert!(shared.reload_from_file(&path).is_err());
+        assert_eq!(shared.get("alice").as_deref(), Some("a"));
+
+        fs::write(&path, "bob:b\n").unwrap();
+        shared.reload_from_file(&path).unwrap();
+        assert!(!shared.contains("alice"));
+        assert!(shared.verify("bob", "b"));
+    }
+}
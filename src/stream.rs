@@ -0,0 +1,296 @@
+//! Reading and writing credentials one entry at a time.
+//!
+//! These are useful for files too large to hold in a [`Creds`] store, or for
+//! building a file from another source, like a database query, without
+//! collecting every credential first.
+
+use std::io::{BufRead, Write};
+
+use hmac::Mac;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{
+    creds::{Creds, CredsError},
+    format::{LineReader, ReadOptions, WriteOptions},
+    integrity::{self, ChecksumReader, HmacSha256},
+};
+
+/// Reads credentials from a reader one line at a time, yielding each
+/// username and password pair as it is parsed.
+///
+/// Lines are parsed like in [`ReadOptions::read`], but duplicate usernames are
+/// yielded each time they appear, since earlier entries are not kept. The
+/// [duplicate policy](ReadOptions::duplicates) and
+/// [metadata](ReadOptions::metadata) options are ignored.
+///
+/// A [checksum](ReadOptions::verify_checksum) can only be checked at the end
+/// of the input, so a mismatch is reported after every entry has been
+/// yielded. After an error, the iterator ends.
+///
+/// See also: [`Creds::stream`]
+pub struct CredsStreamReader<R: BufRead> {
+    reader: R,
+    options: ReadOptions,
+    lines: LineReader,
+    checksum: Option<ChecksumReader>,
+    done: bool,
+}
+
+impl<R: BufRead> CredsStreamReader<R> {
+    /// Creates a stream reader with the default options.
+    pub fn new(reader: R) -> Self {
+        Self::with_options(reader, ReadOptions::new())
+    }
+
+    /// Creates a stream reader with the given options.
+    ///
+    /// See also: [`ReadOptions::stream`]
+    pub fn with_options(reader: R, options: ReadOptions) -> Self {
+        Self {
+            reader,
+            lines: options.line_reader(),
+            checksum: options.checksum_reader(),
+            options,
+            done: false,
+        }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<(String, String)>, CredsError> {
+        while let Some((line_number, line, ending)) = self.lines.next_line(&mut self.reader)? {
+            if let Some(checksum) = &mut self.checksum {
+                checksum.update(&line, ending);
+            }
+            if let Some((username, password)) = self.options.parse_entry(line_number, &line)? {
+                return Ok(Some((username.into_owned(), password.into_owned())));
+            }
+        }
+        self.checksum
+            .take()
+            .map_or(Ok(()), ChecksumReader::finish)?;
+        Ok(None)
+    }
+}
+
+impl<R: BufRead> Iterator for CredsStreamReader<R> {
+    type Item = Result<(String, String), CredsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let entry = self.next_entry().transpose();
+        self.done = !matches!(entry, Some(Ok(_)));
+        entry
+    }
+}
+
+impl Creds {
+    /// Returns an iterator over the username and password pairs in the given
+    /// reader, parsed one line at a time.
+    ///
+    /// See [`CredsStreamReader`].
+    pub fn stream<R: BufRead>(reader: R) -> CredsStreamReader<R> {
+        CredsStreamReader::new(reader)
+    }
+}
+
+/// Writes credentials to a writer as they are produced, without keeping them
+/// in memory.
+///
+/// Each entry is checked and escaped like in [`WriteOptions::write`], and
+/// written immediately. Call [`finish`](CredsStreamWriter::finish) after the
+/// last entry to write the checksum line or HMAC footer, if enabled. Dropping
+/// the writer without finishing leaves them out.
+///
+/// [Metadata](WriteOptions::metadata) and [sorting](WriteOptions::sort_keys)
+/// are not supported, and those options are ignored.
+#[derive(Debug)]
+pub struct CredsStreamWriter<W: Write> {
+    writer: W,
+    options: WriteOptions,
+    line: Zeroizing<Vec<u8>>,
+    checksum: Option<Sha256>,
+    hmac: Option<HmacSha256>,
+}
+
+impl<W: Write> CredsStreamWriter<W> {
+    /// Creates a stream writer with the default options.
+    ///
+    /// See also: [`Creds::write`]
+    pub fn new(writer: W) -> Self {
+        Self::with_options(writer, WriteOptions::new())
+    }
+
+    /// Creates a stream writer with the given options.
+    ///
+    /// See also: [`WriteOptions::stream_writer`]
+    pub fn with_options(writer: W, options: WriteOptions) -> Self {
+        Self {
+            writer,
+            checksum: options.checksum_digest(),
+            options,
+            line: Zeroizing::new(Vec::new()),
+            hmac: None,
+        }
+    }
+
+    /// Creates a stream writer with the default options, which ends the
+    /// output with an HMAC-SHA256 footer computed with the given key.
+    ///
+    /// The output can be read with
+    /// [`Creds::read_verified`].
+    ///
+    /// See also: [`Creds::write_with_hmac`]
+    pub fn with_hmac(writer: W, key: &[u8]) -> Self {
+        Self {
+            hmac: Some(integrity::mac(key, &[])),
+            ..Self::new(writer)
+        }
+    }
+
+    /// Writes a single username and password pair.
+    ///
+    /// Returns [`CredsError::IllegalCharacter`] in the same cases as
+    /// [`WriteOptions::write`], without writing anything.
+    pub fn write_entry(&mut self, username: &str, password: &str) -> Result<(), CredsError> {
+        self.line.clear();
+        self.options
+            .write_entry(&mut *self.line, username, password)?;
+        self.line.push(b'\n');
+        if let Some(checksum) = &mut self.checksum {
+            checksum.update(&*self.line);
+        }
+        if let Some(hmac) = &mut self.hmac {
+            hmac.update(&self.line);
+        }
+        self.writer.write_all(&self.line)?;
+        Ok(())
+    }
+
+    /// Writes the checksum line or HMAC footer, if enabled, and flushes the
+    /// writer.
+    ///
+    /// Returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, CredsError> {
+        if let Some(checksum) = self.checksum.take() {
+            let line = self.options.checksum_line(checksum);
+            self.writer.write_all(line.as_bytes())?;
+        }
+        if let Some(hmac) = self.hmac.take() {
+            let footer = integrity::hmac_footer(hmac);
+            self.writer.write_all(footer.as_bytes())?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{creds::Creds, format::ReadOptions};
+
+    #[test]
+    fn written_entries_read_back() {
+        let mut writer = CredsStreamWriter::new(Vec::new());
+        for index in 0..5 {
+            writer
+                .write_entry(&format!("user{}", index), &format!("pw {}", index))
+                .unwrap();
+        }
+        let contents = writer.finish().unwrap();
+
+        let read = Creds::read(&contents[..]).unwrap();
+        assert_eq!(read.len(), 5);
+        for index in 0..5 {
+            assert!(read.verify(&format!("user{}", index), &format!("pw {}", index)));
+        }
+        assert_eq!(contents, read.to_string_contents().unwrap().into_bytes());
+    }
+
+    #[test]
+    fn illegal_entries_write_nothing() {
+        let mut writer = CredsStreamWriter::new(Vec::new());
+        writer.write_entry("alice", "a").unwrap();
+        assert!(matches!(
+            writer.write_entry("bob:x", "b"),
+            Err(CredsError::IllegalCharacter { .. })
+        ));
+        assert!(writer.write_entry("carol", "line\nbreak").is_err());
+        assert_eq!(writer.finish().unwrap(), b"alice:a\n");
+    }
+
+    #[test]
+    fn checksums_and_footers_are_written_on_finish() {
+        let mut writer =
+            CredsStreamWriter::with_options(Vec::new(), WriteOptions::new().checksum(true));
+        writer.write_entry("alice", "a").unwrap();
+        let contents = writer.finish().unwrap();
+        let read = ReadOptions::new()
+            .verify_checksum(true)
+            .read(&contents[..])
+            .unwrap();
+        assert!(read.verify("alice", "a"));
+
+        let mut writer = CredsStreamWriter::with_hmac(Vec::new(), b"key");
+        writer.write_entry("alice", "a").unwrap();
+        let contents = writer.finish().unwrap();
+        let mut expected = Vec::new();
+        read.write_with_hmac(&mut expected, b"key").unwrap();
+        assert_eq!(contents, expected);
+        assert_eq!(Creds::read_verified(&contents[..], b"key").unwrap(), read);
+    }
+
+    #[test]
+    fn stream_yields_each_entry_in_order() {
+        let contents = "alice:a\n# comment\n\nbob:b:c\r\nalice:again\n";
+        let entries: Vec<(String, String)> = Creds::stream(contents.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected = [("alice", "a"), ("bob", "b:c"), ("alice", "again")];
+        assert_eq!(
+            entries,
+            expected.map(|(username, password)| (username.to_owned(), password.to_owned()))
+        );
+    }
+
+    #[test]
+    fn stream_ends_after_an_error() {
+        let options = ReadOptions::new().strict(true);
+        let mut stream = options.stream("alice:a\nmalformed\nbob:b\n".as_bytes());
+        assert_eq!(
+            stream.next().unwrap().unwrap(),
+            ("alice".to_owned(), "a".to_owned())
+        );
+        assert!(matches!(
+            stream.next(),
+            Some(Err(CredsError::MalformedLine { line_number: 2, .. }))
+        ));
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn stream_checks_the_checksum_at_the_end() {
+        let creds: Creds = [("alice", "a")].into_iter().collect();
+        let mut contents = Vec::new();
+        WriteOptions::new()
+            .checksum(true)
+            .write(&creds, &mut contents)
+            .unwrap();
+        let options = ReadOptions::new().verify_checksum(true);
+        assert_eq!(options.stream(&contents[..]).count(), 1);
+
+        contents[0] = b'A';
+        let mut stream = options.stream(&contents[..]);
+        assert_eq!(
+            stream.next().unwrap().unwrap(),
+            ("Alice".to_owned(), "a".to_owned())
+        );
+        assert!(matches!(
+            stream.next(),
+            Some(Err(CredsError::ChecksumMismatch))
+        ));
+        assert!(stream.next().is_none());
+    }
+}
@@ -0,0 +1,202 @@
+//! Async reading and writing of credential files with Tokio.
+
+use std::path::Path;
+
+use tokio::{
+    fs::File,
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    creds::{Creds, CredsError},
+    file,
+    format::{ReadOptions, WriteOptions},
+    integrity::ChecksumReader,
+};
+
+impl ReadOptions {
+    /// Parses a credential store from the given async reader.
+    ///
+    /// This parses exactly like [`ReadOptions::read`], including the handling
+    /// of I/O errors.
+    pub async fn read_async(
+        &self,
+        mut reader: impl AsyncBufRead + Unpin,
+    ) -> Result<Creds, CredsError> {
+        let mut creds = self.new_creds();
+        let mut lines = self.line_reader();
+        let mut checksum = self.checksum_reader();
+        let mut pending_meta = None;
+        loop {
+            let available = reader
+                .fill_buf()
+                .await
+                .map_err(|error| lines.error(error))?;
+            let (consumed, complete) = lines.feed(available)?;
+            reader.consume(consumed);
+            if !complete {
+                continue;
+            }
+            match lines.take_line()? {
+                Some((line_number, line, ending)) => {
+                    if let Some(checksum) = &mut checksum {
+                        checksum.update(&line, ending);
+                    }
+                    self.read_line(&mut creds, &mut pending_meta, line_number, &line)?;
+                }
+                None => break,
+            }
+        }
+        checksum.map_or(Ok(()), ChecksumReader::finish)?;
+        Ok(creds)
+    }
+
+    /// Parses a credential store from the given file, without blocking the
+    /// async runtime.
+    ///
+    /// See also: [`ReadOptions::read_from_file`]
+    pub async fn read_from_file_async(&self, path: impl AsRef<Path>) -> Result<Creds, CredsError> {
+        let file = File::open(path).await?;
+        self.read_async(BufReader::new(file)).await
+    }
+}
+
+impl WriteOptions {
+    /// Writes the credentials into the given async writer.
+    ///
+    /// This writes exactly like [`WriteOptions::write`].
+    pub async fn write_async(
+        &self,
+        creds: &Creds,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> Result<(), CredsError> {
+        let contents = self.to_bytes(creds)?;
+        writer.write_all(&contents).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Writes the credential store to the given file, without blocking the
+    /// async runtime.
+    ///
+    /// The file is replaced atomically and gets the same permissions as with
+    /// [`WriteOptions::write_to_file`].
+    pub async fn write_to_file_async(
+        &self,
+        creds: &Creds,
+        path: impl AsRef<Path>,
+    ) -> Result<(), CredsError> {
+        let contents = self.to_bytes(creds)?;
+        file::write_atomic_async(path.as_ref(), &contents).await
+    }
+
+    /// Serializes the credentials in memory, so that they can be written
+    /// without blocking.
+    fn to_bytes(&self, creds: &Creds) -> Result<Zeroizing<Vec<u8>>, CredsError> {
+        let mut contents = Zeroizing::new(Vec::new());
+        self.write(creds, &mut *contents)?;
+        Ok(contents)
+    }
+}
+
+impl Creds {
+    /// Parses a credential store from the given async reader.
+    ///
+    /// See also: [`Creds::read`]
+    pub async fn read_async(reader: impl AsyncBufRead + Unpin) -> Result<Self, CredsError> {
+        ReadOptions::new().read_async(reader).await
+    }
+
+    /// Writes the credentials into the given async writer.
+    ///
+    /// See also: [`Creds::write`]
+    pub async fn write_async(&self, writer: impl AsyncWrite + Unpin) -> Result<(), CredsError> {
+        self.write_options().write_async(self, writer).await
+    }
+
+    /// Parses a credential store from the given file, without blocking the
+    /// async runtime.
+    ///
+    /// See also: [`Creds::read_from_file`]
+    pub async fn read_from_file_async(path: impl AsRef<Path>) -> Result<Self, CredsError> {
+        ReadOptions::new().read_from_file_async(path).await
+    }
+
+    /// Writes the credential store to the given file, without blocking the
+    /// async runtime.
+    ///
+    /// See also: [`Creds::write_to_file`]
+    pub async fn write_to_file_async(&self, path: impl AsRef<Path>) -> Result<(), CredsError> {
+        self.write_options().write_to_file_async(self, path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::TestDir;
+
+    fn test_creds() -> Creds {
+        [("alice", "hunter2"), ("bob", "b:c")].into_iter().collect()
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_a_file() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        test_creds().write_to_file_async(&path).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "alice:hunter2\nbob:b:c\n"
+        );
+        let read = Creds::read_from_file_async(&path).await.unwrap();
+        assert_eq!(
+            read.to_string_contents().unwrap(),
+            test_creds().to_string_contents().unwrap()
+        );
+        assert_eq!(
+            read.to_string_contents().unwrap(),
+            Creds::read_from_file(&path)
+                .unwrap()
+                .to_string_contents()
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn parses_like_the_sync_reader() {
+        let contents = "# comment\r\nalice:a\nmalformed\nbob:b";
+        let read = Creds::read_async(contents.as_bytes()).await.unwrap();
+        assert_eq!(
+            read.to_string_contents().unwrap(),
+            Creds::read(contents.as_bytes())
+                .unwrap()
+                .to_string_contents()
+                .unwrap()
+        );
+
+        let options = ReadOptions::new().strict(true);
+        let error = options.read_async(contents.as_bytes()).await.unwrap_err();
+        assert!(matches!(
+            error,
+            CredsError::MalformedLine { line_number: 3, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn writes_like_the_sync_writer() {
+        let mut contents = Vec::new();
+        test_creds().write_async(&mut contents).await.unwrap();
+        assert_eq!(
+            contents,
+            test_creds().to_string_contents().unwrap().as_bytes()
+        );
+
+        let invalid: Creds = [("ali:ce", "a")].into_iter().collect();
+        assert!(matches!(
+            invalid.write_async(Vec::new()).await,
+            Err(CredsError::IllegalCharacter { .. })
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+//! Map keys for usernames that can match case-insensitively.
+
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use indexmap::Equivalent;
+
+/// A username as stored in a [`Creds`](crate::creds::Creds) map.
+///
+/// The original spelling is kept for display and writing. If `fold` is set,
+/// the username hashes and compares by its lowercase form instead, so that
+/// `Alice` and `alice` are the same key. All keys in a map have the same
+/// `fold` setting.
+#[derive(Clone)]
+pub(crate) struct Username {
+    pub(crate) name: String,
+    fold: bool,
+}
+
+impl Username {
+    pub(crate) fn new(name: String, fold: bool) -> Self {
+        Self { name, fold }
+    }
+
+    fn as_ref(&self) -> UsernameRef<'_> {
+        UsernameRef::new(&self.name, self.fold)
+    }
+}
+
+impl fmt::Debug for Username {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name.fmt(f)
+    }
+}
+
+impl PartialEq for Username {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_ref() == other.as_ref()
+    }
+}
+
+impl Eq for Username {}
+
+impl Hash for Username {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_ref().hash(state)
+    }
+}
+
+/// A borrowed [`Username`], for looking up keys without allocating.
+#[derive(Clone, Copy)]
+pub(crate) struct UsernameRef<'a> {
+    name: &'a str,
+    fold: bool,
+}
+
+impl<'a> UsernameRef<'a> {
+    pub(crate) fn new(name: &'a str, fold: bool) -> Self {
+        Self { name, fold }
+    }
+
+    /// Lowercases character by character, which unlike
+    /// [`str::to_lowercase`] does not depend on the surrounding characters.
+    fn folded(self) -> impl Iterator<Item = char> + 'a {
+        self.name.chars().flat_map(char::to_lowercase)
+    }
+}
+
+impl PartialEq for UsernameRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        if self.fold {
+            self.folded().eq(other.folded())
+        } else {
+            self.name == other.name
+        }
+    }
+}
+
+impl Eq for UsernameRef<'_> {}
+
+impl Hash for UsernameRef<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if self.fold {
+            for c in self.folded() {
+                state.write_u32(c as u32);
+            }
+            // Like `str`, end with a byte that cannot be part of the
+            // contents, so that consecutive keys hash unambiguously.
+            state.write_u8(0xff);
+        } else {
+            self.name.hash(state);
+        }
+    }
+}
+
+impl Equivalent<Username> for UsernameRef<'_> {
+    fn equivalent(&self, key: &Username) -> bool {
+        *self == key.as_ref()
+    }
+}
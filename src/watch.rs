@@ -0,0 +1,218 @@
+//! Reloading a credential file automatically when it changes.
+//!
+//! Requires the `watch` feature.
+
+use std::{
+    io,
+    path::Path,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{
+    creds::{Creds, CredsError},
+    shared::SharedCreds,
+};
+
+/// How long a file must go without changes before it is reloaded.
+pub const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// A credential store that is reloaded whenever its file changes on disk.
+///
+/// Changes are debounced: the file is reparsed once it has not changed for
+/// [`DEBOUNCE`], so that a file being written in several steps is not read
+/// halfway. Each reload replaces the credentials with
+/// [`SharedCreds::reload_from_file`], and its result is passed to the
+/// callback. If a reload fails, the callback gets the error and the last
+/// successfully read credentials stay in place.
+///
+/// The containing directory is watched, so files replaced by renaming over
+/// them, like with [`Creds::write_to_file`], are picked up too. Watching
+/// stops when the `WatchedCreds` is dropped.
+pub struct WatchedCreds {
+    creds: SharedCreds,
+    _watcher: RecommendedWatcher,
+}
+
+impl WatchedCreds {
+    /// Reads the credentials from the given file, and starts watching it for
+    /// changes.
+    ///
+    /// Returns an error if the file cannot be read initially or cannot be
+    /// watched. The callback is called from a background thread after each
+    /// reload, but not for the initial read.
+    pub fn new<F>(path: impl AsRef<Path>, callback: F) -> Result<Self, CredsError>
+    where
+        F: FnMut(Result<&SharedCreds, CredsError>) + Send + 'static,
+    {
+        let path = path.as_ref().to_owned();
+        let creds = SharedCreds::new(Creds::read_from_file(&path)?);
+
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher =
+            RecommendedWatcher::new(sender, notify::Config::default()).map_err(watch_error)?;
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        watcher
+            .watch(directory, RecursiveMode::NonRecursive)
+            .map_err(watch_error)?;
+
+        let shared = creds.clone();
+        thread::spawn(move || reload_loop(&path, &shared, &receiver, callback));
+        Ok(Self {
+            creds,
+            _watcher: watcher,
+        })
+    }
+
+    /// Returns a handle to the current credentials.
+    pub fn creds(&self) -> &SharedCreds {
+        &self.creds
+    }
+}
+
+impl Creds {
+    /// Reads the credentials from the given file and reloads them whenever it
+    /// changes.
+    ///
+    /// See [`WatchedCreds`]. Requires the `watch` feature.
+    pub fn watch<F>(path: impl AsRef<Path>, callback: F) -> Result<WatchedCreds, CredsError>
+    where
+        F: FnMut(Result<&SharedCreds, CredsError>) + Send + 'static,
+    {
+        WatchedCreds::new(path, callback)
+    }
+}
+
+/// Waits for changes to the file and reloads it, until the watcher is
+/// dropped.
+fn reload_loop<F>(
+    path: &Path,
+    creds: &SharedCreds,
+    receiver: &Receiver<notify::Result<Event>>,
+    mut callback: F,
+) where
+    F: FnMut(Result<&SharedCreds, CredsError>),
+{
+    let relevant = |event: &notify::Result<Event>| match event {
+        // Reading the file during a reload shows up as access events.
+        Ok(event) => {
+            !event.kind.is_access()
+                && event
+                    .paths
+                    .iter()
+                    .any(|changed| changed.file_name() == path.file_name())
+        }
+        Err(_) => true,
+    };
+
+    while let Ok(event) = receiver.recv() {
+        if !relevant(&event) {
+            continue;
+        }
+        let mut error = event.err();
+        loop {
+            match receiver.recv_timeout(DEBOUNCE) {
+                Ok(event) if relevant(&event) => error = error.or(event.err()),
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        match error {
+            Some(error) => callback(Err(watch_error(error))),
+            None => callback(creds.reload_from_file(path).map(|()| creds)),
+        }
+    }
+}
+
+fn watch_error(error: notify::Error) -> CredsError {
+    match error.kind {
+        notify::ErrorKind::Io(error) => CredsError::Io(error),
+        _ => CredsError::Io(io::Error::other(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Instant};
+
+    use super::*;
+    use crate::file::TestDir;
+
+    /// How long to wait for the watcher before failing a test.
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Starts watching the file, sending the result of each reload to the
+    /// returned receiver as the value of `bob`, or as an error.
+    fn watch(path: &Path) -> (WatchedCreds, Receiver<Result<Option<String>, CredsError>>) {
+        let (sender, receiver) = mpsc::channel();
+        let watched = Creds::watch(path, move |result| {
+            let _ = sender.send(result.map(|creds| creds.get("bob")));
+        })
+        .unwrap();
+        (watched, receiver)
+    }
+
+    /// Waits until a reload passes `done` to the callback.
+    fn wait_for<T>(receiver: &Receiver<T>, mut done: impl FnMut(&T) -> bool) -> T {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let result = receiver
+                .recv_timeout(remaining)
+                .expect("the file was not reloaded in time");
+            if done(&result) {
+                return result;
+            }
+        }
+    }
+
+    #[test]
+    fn rewriting_the_file_reloads_it() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        fs::write(&path, "alice:a\n").unwrap();
+        let (watched, receiver) = watch(&path);
+        assert!(watched.creds().verify("alice", "a"));
+
+        fs::write(&path, "bob:b\n").unwrap();
+        wait_for(
+            &receiver,
+            |result| matches!(result, Ok(Some(password)) if password == "b"),
+        )
+        .unwrap();
+        assert!(!watched.creds().contains("alice"));
+
+        // Replacing the file by renaming over it is picked up as well.
+        let creds: Creds = [("bob", "b2")].into_iter().collect();
+        creds.write_to_file(&path).unwrap();
+        wait_for(
+            &receiver,
+            |result| matches!(result, Ok(Some(password)) if password == "b2"),
+        )
+        .unwrap();
+        assert!(watched.creds().verify("bob", "b2"));
+    }
+
+    #[test]
+    fn malformed_rewrites_keep_the_old_credentials() {
+        let dir = TestDir::new();
+        let path = dir.join("creds");
+        fs::write(&path, "bob:b\n").unwrap();
+        let (watched, receiver) = watch(&path);
+
+        fs::write(&path, "this is not a credential\n").unwrap();
+        let result = wait_for(&receiver, Result::is_err);
+        assert!(matches!(
+            result,
+            Err(CredsError::MalformedLine { line_number: 1 })
+        ));
+        assert!(watched.creds().verify("bob", "b"));
+    }
+}